@@ -0,0 +1,4 @@
+// Embedded migrations must be re-read whenever the directory changes.
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+}
@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS practice_record;
+DROP TABLE IF EXISTS practice_action;
+DROP TABLE IF EXISTS users;
@@ -0,0 +1,22 @@
+-- Tables that used to be created by init_db; IF NOT EXISTS adopts older deployments.
+CREATE TABLE IF NOT EXISTS users (
+    id BIGSERIAL PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    create_time TIMESTAMPTZ NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS practice_action (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    name TEXT NOT NULL,
+    create_time TIMESTAMPTZ NOT NULL,
+    last_finish_time TIMESTAMPTZ
+);
+
+CREATE TABLE IF NOT EXISTS practice_record (
+    id BIGSERIAL PRIMARY KEY,
+    action_id BIGINT NOT NULL REFERENCES practice_action(id),
+    finish_time TIMESTAMPTZ NOT NULL,
+    note TEXT
+);
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS refresh_token (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    family_id UUID NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    create_time TIMESTAMPTZ NOT NULL,
+    expire_time TIMESTAMPTZ NOT NULL,
+    used_time TIMESTAMPTZ,
+    revoked_time TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS refresh_token_family_idx ON refresh_token (family_id);
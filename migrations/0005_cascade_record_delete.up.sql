@@ -0,0 +1,5 @@
+-- Deleting an action removes its records instead of being blocked by them.
+ALTER TABLE practice_record DROP CONSTRAINT IF EXISTS practice_record_action_id_fkey;
+ALTER TABLE practice_record
+    ADD CONSTRAINT practice_record_action_id_fkey
+    FOREIGN KEY (action_id) REFERENCES practice_action(id) ON DELETE CASCADE;
@@ -0,0 +1,2 @@
+ALTER TABLE practice_action DROP COLUMN paused_until;
+ALTER TABLE practice_action DROP COLUMN archived;
@@ -0,0 +1,2 @@
+ALTER TABLE practice_action ADD COLUMN archived BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE practice_action ADD COLUMN paused_until DATE;
@@ -0,0 +1,2 @@
+ALTER TABLE practice_record DROP COLUMN duration_seconds;
+ALTER TABLE practice_record DROP COLUMN quantity;
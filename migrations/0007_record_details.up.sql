@@ -0,0 +1,2 @@
+ALTER TABLE practice_record ADD COLUMN quantity INTEGER;
+ALTER TABLE practice_record ADD COLUMN duration_seconds INTEGER;
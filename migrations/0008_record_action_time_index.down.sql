@@ -0,0 +1 @@
+DROP INDEX IF EXISTS practice_record_action_time_idx;
@@ -0,0 +1,2 @@
+-- Per-action history scans (stats, streaks, record listing) go through this index.
+CREATE INDEX IF NOT EXISTS practice_record_action_time_idx ON practice_record (action_id, finish_time);
@@ -0,0 +1 @@
+ALTER TABLE practice_action DROP COLUMN schedule;
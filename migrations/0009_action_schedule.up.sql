@@ -0,0 +1,2 @@
+-- Recurrence of an action, see `schedule::Schedule` for the accepted shapes.
+ALTER TABLE practice_action ADD COLUMN schedule JSONB NOT NULL DEFAULT '{"type": "daily"}';
@@ -0,0 +1,2 @@
+ALTER TABLE practice_action DROP COLUMN unit;
+ALTER TABLE practice_action DROP COLUMN daily_target;
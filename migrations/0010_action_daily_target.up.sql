@@ -0,0 +1,3 @@
+-- Completions needed per day before the day counts as done.
+ALTER TABLE practice_action ADD COLUMN daily_target INTEGER NOT NULL DEFAULT 1 CHECK (daily_target >= 1);
+ALTER TABLE practice_action ADD COLUMN unit TEXT;
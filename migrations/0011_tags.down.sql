@@ -0,0 +1,2 @@
+DROP TABLE action_tag;
+DROP TABLE tag;
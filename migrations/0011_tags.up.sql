@@ -0,0 +1,16 @@
+-- User-defined tags, attached to actions many-to-many.
+CREATE TABLE tag (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    name TEXT NOT NULL,
+    create_time TIMESTAMPTZ NOT NULL,
+    UNIQUE (user_id, name)
+);
+
+CREATE TABLE action_tag (
+    action_id BIGINT NOT NULL REFERENCES practice_action(id) ON DELETE CASCADE,
+    tag_id BIGINT NOT NULL REFERENCES tag(id) ON DELETE CASCADE,
+    PRIMARY KEY (action_id, tag_id)
+);
+
+CREATE INDEX action_tag_tag_idx ON action_tag (tag_id);
@@ -0,0 +1,10 @@
+-- Manual ordering of a user's actions, seeded in creation order.
+ALTER TABLE practice_action ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+
+UPDATE practice_action a
+SET position = o.position
+FROM (
+    SELECT id, ROW_NUMBER() OVER (PARTITION BY user_id ORDER BY create_time, id) - 1 AS position
+    FROM practice_action
+) o
+WHERE a.id = o.id;
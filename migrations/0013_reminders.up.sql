@@ -0,0 +1,12 @@
+-- Times of day, in the user's time zone, to be reminded of an open action.
+CREATE TABLE reminder (
+    id BIGSERIAL PRIMARY KEY,
+    action_id BIGINT NOT NULL REFERENCES practice_action(id) ON DELETE CASCADE,
+    remind_at TIME NOT NULL,
+    last_sent_date DATE, -- local day the reminder last fired
+    create_time TIMESTAMPTZ NOT NULL,
+    UNIQUE (action_id, remind_at)
+);
+
+-- Where mailed reminders go.
+ALTER TABLE users ADD COLUMN email TEXT;
@@ -0,0 +1,3 @@
+ALTER TABLE practice_action DROP COLUMN streak_checked_date;
+DROP TABLE webhook_delivery;
+DROP TABLE webhook;
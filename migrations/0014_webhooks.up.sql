@@ -0,0 +1,35 @@
+-- Per-user subscriptions to events, delivered as signed HTTP POSTs.
+CREATE TABLE webhook (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    events TEXT[] NOT NULL,
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    create_time TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX webhook_user_idx ON webhook (user_id);
+
+-- Outbox of events to deliver, written in the same transaction as the change
+-- that caused them, and kept afterwards as the delivery log.
+CREATE TABLE webhook_delivery (
+    id BIGSERIAL PRIMARY KEY,
+    webhook_id BIGINT NOT NULL REFERENCES webhook(id) ON DELETE CASCADE,
+    event TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'delivered', 'failed')),
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_time TIMESTAMPTZ NOT NULL,
+    last_attempt_time TIMESTAMPTZ,
+    response_status INTEGER,
+    last_error TEXT,
+    create_time TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX webhook_delivery_webhook_idx ON webhook_delivery (webhook_id, id);
+CREATE INDEX webhook_delivery_pending_idx ON webhook_delivery (next_attempt_time)
+    WHERE status = 'pending';
+
+-- Local day on which the action was last checked for a broken streak.
+ALTER TABLE practice_action ADD COLUMN streak_checked_date DATE;
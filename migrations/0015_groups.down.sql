@@ -0,0 +1,17 @@
+-- Reminders and records of other members go with their access.
+DELETE FROM reminder rm
+USING practice_action a
+WHERE rm.action_id = a.id AND rm.user_id <> a.user_id;
+ALTER TABLE reminder DROP CONSTRAINT reminder_action_id_user_id_remind_at_key;
+ALTER TABLE reminder ADD UNIQUE (action_id, remind_at);
+ALTER TABLE reminder DROP COLUMN user_id;
+
+DELETE FROM practice_record r
+USING practice_action a
+WHERE r.action_id = a.id AND r.user_id <> a.user_id;
+DROP INDEX practice_record_user_id_finish_time_idx;
+ALTER TABLE practice_record DROP COLUMN user_id;
+
+ALTER TABLE practice_action DROP COLUMN group_id;
+DROP TABLE group_member;
+DROP TABLE practice_group;
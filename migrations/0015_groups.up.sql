@@ -0,0 +1,38 @@
+-- Groups of users sharing actions.
+CREATE TABLE practice_group (
+    id BIGSERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    create_time TIMESTAMPTZ NOT NULL
+);
+
+CREATE TABLE group_member (
+    group_id BIGINT NOT NULL REFERENCES practice_group(id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role TEXT NOT NULL CHECK (role IN ('owner', 'admin', 'member')),
+    join_time TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (group_id, user_id)
+);
+
+CREATE INDEX group_member_user_id_idx ON group_member(user_id);
+
+-- Actions owned by a group are visible to all of its members; user_id stays
+-- the member who created it.
+ALTER TABLE practice_action
+    ADD COLUMN group_id BIGINT REFERENCES practice_group(id) ON DELETE SET NULL;
+
+-- Who completed a record, so that members of a group action count separately.
+ALTER TABLE practice_record ADD COLUMN user_id BIGINT REFERENCES users(id) ON DELETE CASCADE;
+UPDATE practice_record r SET user_id = a.user_id
+FROM practice_action a
+WHERE r.action_id = a.id;
+ALTER TABLE practice_record ALTER COLUMN user_id SET NOT NULL;
+CREATE INDEX practice_record_user_id_finish_time_idx ON practice_record(user_id, finish_time);
+
+-- Reminders belong to the member who set them.
+ALTER TABLE reminder ADD COLUMN user_id BIGINT REFERENCES users(id) ON DELETE CASCADE;
+UPDATE reminder rm SET user_id = a.user_id
+FROM practice_action a
+WHERE rm.action_id = a.id;
+ALTER TABLE reminder ALTER COLUMN user_id SET NOT NULL;
+ALTER TABLE reminder DROP CONSTRAINT reminder_action_id_remind_at_key;
+ALTER TABLE reminder ADD UNIQUE (action_id, user_id, remind_at);
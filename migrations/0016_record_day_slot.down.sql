@@ -0,0 +1,2 @@
+DROP INDEX practice_record_day_slot_key;
+ALTER TABLE practice_record DROP COLUMN day_slot, DROP COLUMN local_day;
@@ -0,0 +1,32 @@
+-- Each completion takes one of the action's daily_target slots of its local
+-- day, so that the database rejects a completion beyond the target even when
+-- two arrive at once. The day is fixed in the user's time zone at the time
+-- of recording.
+ALTER TABLE practice_record ADD COLUMN local_day DATE, ADD COLUMN day_slot INT;
+
+UPDATE practice_record r
+SET local_day = (r.finish_time AT TIME ZONE u.time_zone)::date
+FROM users u
+WHERE r.user_id = u.id;
+
+WITH numbered AS (
+    SELECT
+        id,
+        ROW_NUMBER() OVER (
+            PARTITION BY action_id, user_id, local_day
+            ORDER BY finish_time, id
+        ) AS slot
+    FROM practice_record
+)
+UPDATE practice_record r
+SET day_slot = n.slot
+FROM numbered n
+WHERE r.id = n.id;
+
+ALTER TABLE practice_record
+    ALTER COLUMN local_day SET NOT NULL,
+    ALTER COLUMN day_slot SET NOT NULL,
+    ADD CONSTRAINT practice_record_day_slot_check CHECK (day_slot >= 1);
+
+CREATE UNIQUE INDEX practice_record_day_slot_key
+    ON practice_record (action_id, user_id, local_day, day_slot);
@@ -0,0 +1,14 @@
+-- Responses to requests sent with an Idempotency-Key, replayed on retries.
+CREATE TABLE idempotency_key (
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    fingerprint TEXT NOT NULL, -- hash of method, path and body
+    status INT, -- NULL while the first request is still running
+    content_type TEXT,
+    body BYTEA,
+    create_time TIMESTAMPTZ NOT NULL,
+    expire_time TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (user_id, key)
+);
+
+CREATE INDEX idempotency_key_expire_time_idx ON idempotency_key (expire_time);
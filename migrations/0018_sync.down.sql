@@ -0,0 +1,18 @@
+DROP TRIGGER practice_record_sync_tombstone ON practice_record;
+DROP TRIGGER practice_record_sync_change ON practice_record;
+DROP TRIGGER practice_action_sync_tombstone ON practice_action;
+DROP TRIGGER practice_action_sync_change ON practice_action;
+DROP FUNCTION record_sync_tombstone();
+DROP FUNCTION track_sync_change();
+DROP TABLE sync_tombstone;
+ALTER TABLE practice_record
+    DROP COLUMN change_xid,
+    DROP COLUMN change_seq,
+    DROP COLUMN update_time,
+    DROP COLUMN client_id;
+ALTER TABLE practice_action
+    DROP COLUMN change_xid,
+    DROP COLUMN change_seq,
+    DROP COLUMN update_time,
+    DROP COLUMN client_id;
+DROP SEQUENCE sync_change_seq;
@@ -0,0 +1,101 @@
+-- Change tracking for the sync API. Every insert or update of an action or
+-- record takes the next value of sync_change_seq together with the id of its
+-- transaction, and every delete leaves a tombstone, so that clients can ask
+-- for what changed since their last sync. Rows are also addressed by a
+-- client_id, which offline clients generate for what they create.
+CREATE SEQUENCE sync_change_seq;
+
+ALTER TABLE practice_action
+    ADD COLUMN client_id UUID NOT NULL DEFAULT gen_random_uuid(),
+    ADD COLUMN update_time TIMESTAMPTZ, -- last change of the synced fields
+    ADD COLUMN change_seq BIGINT,
+    ADD COLUMN change_xid BIGINT;
+UPDATE practice_action
+SET update_time = create_time,
+    change_seq = nextval('sync_change_seq'),
+    change_xid = pg_current_xact_id()::TEXT::BIGINT;
+ALTER TABLE practice_action
+    ALTER COLUMN update_time SET NOT NULL,
+    ALTER COLUMN update_time SET DEFAULT NOW(),
+    ALTER COLUMN change_seq SET NOT NULL,
+    ALTER COLUMN change_xid SET NOT NULL,
+    ADD CONSTRAINT practice_action_client_id_key UNIQUE (client_id);
+CREATE INDEX practice_action_change_seq_idx ON practice_action (change_seq);
+
+ALTER TABLE practice_record
+    ADD COLUMN client_id UUID NOT NULL DEFAULT gen_random_uuid(),
+    ADD COLUMN update_time TIMESTAMPTZ,
+    ADD COLUMN change_seq BIGINT,
+    ADD COLUMN change_xid BIGINT;
+UPDATE practice_record
+SET update_time = finish_time,
+    change_seq = nextval('sync_change_seq'),
+    change_xid = pg_current_xact_id()::TEXT::BIGINT;
+ALTER TABLE practice_record
+    ALTER COLUMN update_time SET NOT NULL,
+    ALTER COLUMN update_time SET DEFAULT NOW(),
+    ALTER COLUMN change_seq SET NOT NULL,
+    ALTER COLUMN change_xid SET NOT NULL,
+    ADD CONSTRAINT practice_record_client_id_key UNIQUE (client_id);
+CREATE INDEX practice_record_change_seq_idx ON practice_record (user_id, change_seq);
+
+CREATE TABLE sync_tombstone (
+    user_id BIGINT NOT NULL,
+    kind TEXT NOT NULL CHECK (kind IN ('action', 'record')),
+    client_id UUID NOT NULL,
+    group_id BIGINT, -- of a deleted group action, whose members see the tombstone
+    delete_time TIMESTAMPTZ NOT NULL,
+    change_seq BIGINT NOT NULL,
+    change_xid BIGINT NOT NULL,
+    PRIMARY KEY (user_id, kind, client_id)
+);
+CREATE INDEX sync_tombstone_change_seq_idx ON sync_tombstone (change_seq);
+
+-- Columns given as trigger arguments are not synced, so changing only them
+-- is not a change.
+CREATE FUNCTION track_sync_change() RETURNS trigger AS $$
+DECLARE
+    ignored TEXT[] := TG_ARGV || ARRAY['change_seq', 'change_xid'];
+BEGIN
+    IF TG_OP = 'UPDATE' THEN
+        IF to_jsonb(OLD) - ignored = to_jsonb(NEW) - ignored THEN
+            RETURN NEW;
+        END IF;
+    END IF;
+    NEW.change_seq := nextval('sync_change_seq');
+    NEW.change_xid := pg_current_xact_id()::TEXT::BIGINT;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+-- The kind of the deleted row is the trigger argument.
+CREATE FUNCTION record_sync_tombstone() RETURNS trigger AS $$
+BEGIN
+    INSERT INTO sync_tombstone (
+        client_id, kind, user_id, group_id, delete_time, change_seq, change_xid
+    )
+    VALUES (
+        OLD.client_id, TG_ARGV[0], OLD.user_id, (to_jsonb(OLD) ->> 'group_id')::BIGINT, NOW(),
+        nextval('sync_change_seq'), pg_current_xact_id()::TEXT::BIGINT
+    )
+    ON CONFLICT (user_id, kind, client_id) DO UPDATE
+    SET group_id = EXCLUDED.group_id,
+        delete_time = EXCLUDED.delete_time,
+        change_seq = EXCLUDED.change_seq,
+        change_xid = EXCLUDED.change_xid;
+    RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER practice_action_sync_change
+    BEFORE INSERT OR UPDATE ON practice_action
+    FOR EACH ROW EXECUTE FUNCTION track_sync_change('streak_checked_date');
+CREATE TRIGGER practice_action_sync_tombstone
+    AFTER DELETE ON practice_action
+    FOR EACH ROW EXECUTE FUNCTION record_sync_tombstone('action');
+CREATE TRIGGER practice_record_sync_change
+    BEFORE INSERT OR UPDATE ON practice_record
+    FOR EACH ROW EXECUTE FUNCTION track_sync_change();
+CREATE TRIGGER practice_record_sync_tombstone
+    AFTER DELETE ON practice_record
+    FOR EACH ROW EXECUTE FUNCTION record_sync_tombstone('record');
@@ -0,0 +1 @@
+DROP INDEX practice_record_user_id_local_day_idx;
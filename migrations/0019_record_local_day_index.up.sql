@@ -0,0 +1,2 @@
+-- Stats, streaks and calendars bucket records by their stored local day.
+CREATE INDEX practice_record_user_id_local_day_idx ON practice_record(user_id, local_day);
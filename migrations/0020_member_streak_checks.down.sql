@@ -0,0 +1,10 @@
+DROP TRIGGER practice_action_sync_change ON practice_action;
+ALTER TABLE practice_action ADD COLUMN streak_checked_date DATE;
+UPDATE practice_action a
+SET streak_checked_date = c.checked_date
+FROM streak_check c
+WHERE c.action_id = a.id AND c.user_id = a.user_id;
+CREATE TRIGGER practice_action_sync_change
+    BEFORE INSERT OR UPDATE ON practice_action
+    FOR EACH ROW EXECUTE FUNCTION track_sync_change('streak_checked_date');
+DROP TABLE streak_check;
@@ -0,0 +1,20 @@
+-- Local day on which an action was last checked for a broken streak, per user
+-- who can see it, so that every member of a group is checked for its actions.
+CREATE TABLE streak_check (
+    action_id BIGINT NOT NULL REFERENCES practice_action(id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    checked_date DATE NOT NULL,
+    PRIMARY KEY (action_id, user_id)
+);
+
+INSERT INTO streak_check (action_id, user_id, checked_date)
+SELECT id, user_id, streak_checked_date
+FROM practice_action
+WHERE streak_checked_date IS NOT NULL;
+
+-- The check no longer touches practice_action, so no column needs ignoring.
+DROP TRIGGER practice_action_sync_change ON practice_action;
+ALTER TABLE practice_action DROP COLUMN streak_checked_date;
+CREATE TRIGGER practice_action_sync_change
+    BEFORE INSERT OR UPDATE ON practice_action
+    FOR EACH ROW EXECUTE FUNCTION track_sync_change();
@@ -0,0 +1 @@
+ALTER TABLE reminder DROP COLUMN retry_time, DROP COLUMN attempts;
@@ -0,0 +1,5 @@
+-- Failed sends of a reminder are retried with a growing delay, up to a limit
+-- per day.
+ALTER TABLE reminder
+    ADD COLUMN attempts INT NOT NULL DEFAULT 0, -- failed sends on the current day
+    ADD COLUMN retry_time TIMESTAMPTZ;
@@ -4,17 +4,170 @@ use axum::{
     http::{request::Parts, StatusCode},
 };
 use bcrypt::{hash, verify, DEFAULT_COST};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::{
+    decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
 
+use crate::db::is_token_revoked;
 use crate::models::Claims;
-use crate::AppError;
-use std::collections::HashSet;
+use crate::{AppError, AppState};
+use std::collections::HashMap;
 use std::env;
+use std::fs;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Secrets that ship with the repository and must never sign production tokens.
+const KNOWN_DEFAULT_SECRETS: [&str; 2] = ["ThisISMYSectKeyXHaxx1234", "your-secret-key-here"];
+const DEV_SECRET: &str = "ThisISMYSectKeyXHaxx1234";
+const DEFAULT_KID: &str = "default";
 
 lazy_static::lazy_static! {
-    static ref JWT_SECRET: Vec<u8> = env::var("JWT_SECRET")
-        .unwrap_or_else(|_| "ThisISMYSectKeyXHaxx1234".to_string())
-        .into_bytes();
+    pub static ref ACCESS_TOKEN_TTL: Duration = Duration::seconds(
+        env::var("ACCESS_TOKEN_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(15 * 60),
+    );
+    pub static ref REFRESH_TOKEN_TTL: Duration = Duration::seconds(
+        env::var("REFRESH_TOKEN_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30 * 24 * 60 * 60),
+    );
+}
+
+/// Signing key plus every key still accepted for verification, indexed by `kid`.
+///
+/// Configuration:
+/// - `JWT_ALGORITHM`: `HS256` (default), `RS256` or `EdDSA`.
+/// - `JWT_KEYS`: comma separated `kid:value` pairs. For HS256 the value is the
+///   secret, for RS256/EdDSA it is the path of a PEM public key. For HS256,
+///   `JWT_SECRET` alone is accepted as a single key with kid `default`.
+/// - `JWT_ACTIVE_KID`: kid used to sign new tokens (default: first key).
+/// - `JWT_PRIVATE_KEY_FILE`: PEM private key of the active kid (RS256/EdDSA).
+///
+/// Retiring a key is a matter of signing with a new kid and dropping the old
+/// entry once the tokens it signed have expired.
+pub struct JwtKeys {
+    algorithm: Algorithm,
+    active_kid: String,
+    encoding_key: EncodingKey,
+    decoding_keys: HashMap<String, DecodingKey>,
+}
+
+impl JwtKeys {
+    /// Loads the keys from the environment. In production (`APP_ENV=production`)
+    /// a missing or well-known HS256 secret is an error instead of a warning.
+    pub fn from_env() -> Result<Self, String> {
+        let production = env::var("APP_ENV").is_ok_and(|v| v.eq_ignore_ascii_case("production"));
+
+        let algorithm = match env::var("JWT_ALGORITHM").as_deref() {
+            Err(_) | Ok("HS256") => Algorithm::HS256,
+            Ok("RS256") => Algorithm::RS256,
+            Ok("EdDSA") => Algorithm::EdDSA,
+            Ok(other) => return Err(format!("Unsupported JWT_ALGORITHM: {}", other)),
+        };
+
+        let keys = match env::var("JWT_KEYS") {
+            Ok(value) => parse_key_list(&value)?,
+            Err(_) if algorithm == Algorithm::HS256 => match env::var("JWT_SECRET") {
+                Ok(secret) => vec![(DEFAULT_KID.to_string(), secret)],
+                Err(_) if production => {
+                    return Err("JWT_SECRET or JWT_KEYS must be set in production".to_string())
+                }
+                Err(_) => {
+                    warn!("JWT_SECRET is not set, using the built-in development secret");
+                    vec![(DEFAULT_KID.to_string(), DEV_SECRET.to_string())]
+                }
+            },
+            Err(_) => return Err("JWT_KEYS must list the public keys".to_string()),
+        };
+
+        let active_kid = match env::var("JWT_ACTIVE_KID") {
+            Ok(kid) => kid,
+            Err(_) => keys[0].0.clone(),
+        };
+
+        let mut decoding_keys = HashMap::new();
+        let mut encoding_key = None;
+        for (kid, value) in &keys {
+            let decoding_key = if algorithm == Algorithm::HS256 {
+                if KNOWN_DEFAULT_SECRETS.contains(&value.as_str()) {
+                    if production {
+                        return Err(format!("JWT key '{}' uses a built-in default secret", kid));
+                    }
+                    warn!("JWT key '{}' uses a built-in default secret", kid);
+                }
+                if kid == &active_kid {
+                    encoding_key = Some(EncodingKey::from_secret(value.as_bytes()));
+                }
+                DecodingKey::from_secret(value.as_bytes())
+            } else {
+                let pem = read_pem(value)?;
+                let decoding_key = if algorithm == Algorithm::RS256 {
+                    DecodingKey::from_rsa_pem(&pem)
+                } else {
+                    DecodingKey::from_ed_pem(&pem)
+                };
+                decoding_key.map_err(|e| format!("Invalid public key '{}': {}", kid, e))?
+            };
+            decoding_keys.insert(kid.clone(), decoding_key);
+        }
+
+        if !decoding_keys.contains_key(&active_kid) {
+            return Err(format!(
+                "JWT_ACTIVE_KID '{}' is not in JWT_KEYS",
+                active_kid
+            ));
+        }
+
+        if algorithm != Algorithm::HS256 {
+            let path = env::var("JWT_PRIVATE_KEY_FILE")
+                .map_err(|_| "JWT_PRIVATE_KEY_FILE must be set".to_string())?;
+            let pem = read_pem(&path)?;
+            let key = if algorithm == Algorithm::RS256 {
+                EncodingKey::from_rsa_pem(&pem)
+            } else {
+                EncodingKey::from_ed_pem(&pem)
+            };
+            encoding_key = Some(key.map_err(|e| format!("Invalid private key: {}", e))?);
+        }
+
+        Ok(JwtKeys {
+            algorithm,
+            active_kid,
+            encoding_key: encoding_key.expect("active key is present"),
+            decoding_keys,
+        })
+    }
+}
+
+fn parse_key_list(value: &str) -> Result<Vec<(String, String)>, String> {
+    let keys = value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.split_once(':') {
+            Some((kid, key)) if !kid.is_empty() && !key.is_empty() => {
+                Ok((kid.to_string(), key.to_string()))
+            }
+            _ => Err(format!("Invalid JWT_KEYS entry: {}", entry)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if keys.is_empty() {
+        return Err("JWT_KEYS is empty".to_string());
+    }
+    Ok(keys)
+}
+
+fn read_pem(path: &str) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|e| format!("Failed to read key file {}: {}", path, e))
 }
 
 pub fn hash_password(password: &str) -> Result<String, bcrypt::BcryptError> {
@@ -25,25 +178,60 @@ pub fn verify_password(password: &str, hash: &str) -> bool {
     verify(password.as_bytes(), hash).unwrap_or(false)
 }
 
-pub fn create_token(user_id: i64) -> Result<String, jsonwebtoken::errors::Error> {
-    let claims = Claims { sub: user_id };
-    let header = Header::default();
+pub fn create_token(
+    keys: &JwtKeys,
+    user_id: i64,
+    token_version: i32,
+    session_id: Uuid,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let now = OffsetDateTime::now_utc();
+    let claims = Claims {
+        sub: user_id,
+        exp: (now + *ACCESS_TOKEN_TTL).unix_timestamp(),
+        iat: now.unix_timestamp(),
+        jti: Uuid::new_v4(),
+        sid: session_id,
+        ver: token_version,
+    };
+    let mut header = Header::new(keys.algorithm);
+    header.kid = Some(keys.active_kid.clone());
+
+    encode(&header, &claims, &keys.encoding_key)
+}
+
+/// Returns a new opaque refresh token together with the hash we persist for it.
+/// Only the hash is stored, so a database leak does not hand out usable tokens.
+pub fn generate_refresh_token() -> (String, String) {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = to_hex(&bytes);
+    let token_hash = hash_refresh_token(&token);
+    (token, token_hash)
+}
+
+pub fn hash_refresh_token(token: &str) -> String {
+    to_hex(&Sha256::digest(token.as_bytes()))
+}
 
-    encode(&header, &claims, &EncodingKey::from_secret(&JWT_SECRET))
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
 }
 
 pub struct AuthUser {
     pub user_id: i64,
+    pub jti: Uuid,
+    pub session_id: Uuid,
+    pub expire_time: OffsetDateTime,
 }
 
 #[async_trait]
-impl<S> FromRequestParts<S> for AuthUser
-where
-    S: Send + Sync,
-{
+impl FromRequestParts<Arc<AppState>> for AuthUser {
     type Rejection = AppError;
 
-    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
         let auth_header = parts
             .headers
             .get("Authorization")
@@ -56,22 +244,37 @@ where
                 )
             })?;
 
-        // Create validation that doesn't check for expiration
-        let mut validation = Validation::new(jsonwebtoken::Algorithm::HS256);
-        validation.required_spec_claims = HashSet::new();
-        validation.validate_exp = false; // Disable expiration time validation
+        let invalid = || AppError(StatusCode::UNAUTHORIZED, "Invalid token".to_string());
+
+        // Pick the verification key by kid; tokens without one predate rotation
+        let keys = &state.jwt_keys;
+        let header = decode_header(auth_header).map_err(|_| invalid())?;
+        let kid = header.kid.as_deref().unwrap_or(&keys.active_kid);
+        let decoding_key = keys.decoding_keys.get(kid).ok_or_else(invalid)?;
+
+        // Expiration is required and checked; we don't issue an audience claim
+        let mut validation = Validation::new(keys.algorithm);
         validation.validate_aud = false;
 
         // Decode and validate the token
-        let token_data = decode::<Claims>(
-            auth_header,
-            &DecodingKey::from_secret(&JWT_SECRET),
-            &validation,
-        )
-        .map_err(|_| AppError(StatusCode::UNAUTHORIZED, "Invalid token".to_string()))?;
+        let token_data =
+            decode::<Claims>(auth_header, decoding_key, &validation).map_err(|_| invalid())?;
+        let claims = token_data.claims;
+
+        if is_token_revoked(&state.pool, claims.sub, claims.jti, claims.ver).await? {
+            return Err(AppError(
+                StatusCode::UNAUTHORIZED,
+                "Token has been revoked".to_string(),
+            ));
+        }
+
+        let expire_time = OffsetDateTime::from_unix_timestamp(claims.exp).map_err(|_| invalid())?;
 
         Ok(AuthUser {
-            user_id: token_data.claims.sub,
+            user_id: claims.sub,
+            jti: claims.jti,
+            session_id: claims.sid,
+            expire_time,
         })
     }
 }
@@ -1,84 +1,138 @@
-use sqlx::PgPool;
-use time::OffsetDateTime;
+use serde_json::json;
+use sqlx::types::Json;
+use sqlx::{PgConnection, PgPool};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use time::{Date, OffsetDateTime, Time};
+use tokio_stream::Stream;
+use uuid::Uuid;
 
-use crate::models::{ActionWithStats, PracticeAction, PracticeRecord, User};
+use crate::migrate::MIGRATOR;
+use crate::models::{
+    ActionPerformance, ActionSort, ActionWithStats, CalendarDay, CreateActionRequest,
+    DeliveryStatus, DueReminder, ExportAction, ExportRecord, FinishActionRequest, FinishOutcome,
+    Granularity, Group, GroupMember, GroupRole, IdempotencyRecord, ImportAction, ImportTarget,
+    ImportedRecord, LeaderboardEntry, PendingDelivery, PeriodCount, PracticeAction, PracticeRecord,
+    RecordCursor, RefreshOutcome, RefreshToken, Reminder, ReorderOutcome, StreakCheck, SyncAction,
+    SyncActionChange, SyncKind, SyncOutcome, SyncRecord, SyncRecordChange, SyncTombstone, Tag,
+    TagWithStats, UpdateActionRequest, UpdateRecordRequest, User, Webhook, WebhookDelivery,
+    WebhookEvent,
+};
 
+/// Connects to the database and applies pending migrations.
 pub async fn init_db(db_url: &str) -> Result<PgPool, sqlx::Error> {
     let pool = PgPool::connect(db_url).await?;
+    MIGRATOR.run(&pool).await?;
+    Ok(pool)
+}
 
-    sqlx::query(
+pub async fn create_user(
+    pool: &PgPool,
+    username: &str,
+    password_hash: &str,
+    time_zone: &str,
+) -> Result<User, sqlx::Error> {
+    let now = OffsetDateTime::now_utc();
+
+    let user = sqlx::query_as::<_, User>(
         r#"
-        CREATE TABLE IF NOT EXISTS users (
-            id BIGSERIAL PRIMARY KEY,
-            username TEXT NOT NULL UNIQUE,
-            password_hash TEXT NOT NULL,
-            create_time TIMESTAMPTZ NOT NULL
-        )
+        INSERT INTO users (username, password_hash, time_zone, create_time)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, username, password_hash, time_zone, email, token_version, create_time
         "#,
     )
-    .execute(&pool)
+    .bind(username)
+    .bind(password_hash)
+    .bind(time_zone)
+    .bind(now)
+    .fetch_one(pool)
     .await?;
 
-    sqlx::query(
+    Ok(user)
+}
+
+pub async fn get_user_by_id(pool: &PgPool, user_id: i64) -> Result<Option<User>, sqlx::Error> {
+    let user = sqlx::query_as::<_, User>(
         r#"
-        CREATE TABLE IF NOT EXISTS practice_action (
-            id BIGSERIAL PRIMARY KEY,
-            user_id BIGINT NOT NULL REFERENCES users(id),
-            name TEXT NOT NULL,
-            create_time TIMESTAMPTZ NOT NULL,
-            last_finish_time TIMESTAMPTZ
-        )
+        SELECT id, username, password_hash, time_zone, email, token_version, create_time
+        FROM users
+        WHERE id = $1
         "#,
     )
-    .execute(&pool)
+    .bind(user_id)
+    .fetch_optional(pool)
     .await?;
 
-    sqlx::query(
+    Ok(user)
+}
+
+pub async fn update_user_time_zone(
+    pool: &PgPool,
+    user_id: i64,
+    time_zone: &str,
+) -> Result<User, sqlx::Error> {
+    let user = sqlx::query_as::<_, User>(
         r#"
-        CREATE TABLE IF NOT EXISTS practice_record (
-            id BIGSERIAL PRIMARY KEY,
-            action_id BIGINT NOT NULL REFERENCES practice_action(id),
-            finish_time TIMESTAMPTZ NOT NULL,
-            note TEXT
-        )
+        UPDATE users
+        SET time_zone = $1
+        WHERE id = $2
+        RETURNING id, username, password_hash, time_zone, email, token_version, create_time
         "#,
     )
-    .execute(&pool)
+    .bind(time_zone)
+    .bind(user_id)
+    .fetch_one(pool)
     .await?;
 
-    Ok(pool)
+    Ok(user)
 }
 
-pub async fn create_user(
+pub async fn update_user_email(
     pool: &PgPool,
-    username: &str,
-    password_hash: &str,
+    user_id: i64,
+    email: Option<&str>,
 ) -> Result<User, sqlx::Error> {
-    let now = OffsetDateTime::now_utc();
-
     let user = sqlx::query_as::<_, User>(
         r#"
-        INSERT INTO users (username, password_hash, create_time)
-        VALUES ($1, $2, $3)
-        RETURNING id, username, password_hash, create_time
+        UPDATE users
+        SET email = $1
+        WHERE id = $2
+        RETURNING id, username, password_hash, time_zone, email, token_version, create_time
         "#,
     )
-    .bind(username)
-    .bind(password_hash)
-    .bind(now)
+    .bind(email)
+    .bind(user_id)
     .fetch_one(pool)
     .await?;
 
     Ok(user)
 }
 
+/// Checks the name against the IANA zones known to the database, so that every
+/// zone we store can later be used in `AT TIME ZONE`.
+pub async fn is_valid_time_zone(pool: &PgPool, time_zone: &str) -> Result<bool, sqlx::Error> {
+    let exists: Option<bool> = sqlx::query_scalar(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM pg_timezone_names
+            WHERE name = $1
+        )
+        "#,
+    )
+    .bind(time_zone)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(exists.unwrap_or(false))
+}
+
 pub async fn get_user_by_username(
     pool: &PgPool,
     username: &str,
 ) -> Result<Option<User>, sqlx::Error> {
     let user = sqlx::query_as::<_, User>(
         r#"
-        SELECT id, username, password_hash, create_time
+        SELECT id, username, password_hash, time_zone, email, token_version, create_time
         FROM users
         WHERE username = $1
         "#,
@@ -93,28 +147,60 @@ pub async fn get_user_by_username(
 pub async fn create_practice_action(
     pool: &PgPool,
     user_id: i64,
-    name: String,
+    req: CreateActionRequest,
 ) -> Result<PracticeAction, sqlx::Error> {
     let now = OffsetDateTime::now_utc();
-    println!("now: {}, uid: {} name {}", now, user_id, name);
+    println!("now: {}, uid: {} name {}", now, user_id, req.name);
 
-    let action = sqlx::query_as::<_, PracticeAction>(
+    let mut tx = pool.begin().await?;
+
+    let mut action = sqlx::query_as::<_, PracticeAction>(
         r#"
-        INSERT INTO practice_action (user_id, name, create_time)
-        VALUES ($1, $2, $3)
-        RETURNING id, user_id, name, create_time, last_finish_time
+        INSERT INTO practice_action (
+            user_id, name, create_time, schedule, daily_target, unit, position, group_id
+        )
+        VALUES (
+            $1, $2, $3, $4, $5, $6,
+            (
+                SELECT COALESCE(MAX(position) + 1, 0) FROM practice_action
+                WHERE user_id = $1 AND group_id IS NULL
+            ),
+            $7
+        )
+        RETURNING id, user_id, name, create_time, last_finish_time, archived, paused_until, schedule,
+            daily_target, unit, position, group_id
         "#,
     )
     .bind(user_id)
-    .bind(name)
+    .bind(req.name)
     .bind(now)
-    .fetch_one(pool)
+    .bind(Json(req.schedule.unwrap_or_default()))
+    .bind(req.daily_target.unwrap_or(1))
+    .bind(req.unit)
+    .bind(req.group_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if let Some(tags) = req.tags {
+        set_action_tags(&mut tx, user_id, action.id, &tags).await?;
+        action.tags = tags;
+    }
+
+    enqueue_webhook_event(
+        &mut tx,
+        user_id,
+        WebhookEvent::ActionCreated,
+        &json!({ "action": &action }),
+    )
     .await?;
+
+    tx.commit().await?;
     println!("action created: {:?}", action);
 
     Ok(action)
 }
 
+/// The action if the user owns it, or is a member of the group owning it.
 pub async fn get_practice_action(
     pool: &PgPool,
     user_id: i64,
@@ -122,9 +208,17 @@ pub async fn get_practice_action(
 ) -> Result<Option<PracticeAction>, sqlx::Error> {
     let action = sqlx::query_as::<_, PracticeAction>(
         r#"
-        SELECT id, user_id, name, create_time, last_finish_time
-        FROM practice_action 
-        WHERE id = $1 AND user_id = $2
+        SELECT id, user_id, name, create_time, last_finish_time, archived, paused_until, schedule,
+            daily_target, unit, position, group_id
+        FROM practice_action a
+        WHERE id = $1
+        AND (
+            (a.group_id IS NULL AND a.user_id = $2)
+            OR EXISTS (
+                SELECT 1 FROM group_member m
+                WHERE m.group_id = a.group_id AND m.user_id = $2
+            )
+        )
         "#,
     )
     .bind(id)
@@ -135,21 +229,252 @@ pub async fn get_practice_action(
     Ok(action)
 }
 
+/// Applies the given changes; fields left as `None` keep their current value.
+/// Group actions can be changed by their creator and the group's admins.
+pub async fn update_practice_action(
+    pool: &PgPool,
+    user_id: i64,
+    id: i64,
+    req: UpdateActionRequest,
+) -> Result<Option<PracticeAction>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let action = sqlx::query_as::<_, PracticeAction>(
+        r#"
+        UPDATE practice_action a
+        SET name = COALESCE($1, name),
+            archived = COALESCE($2, archived),
+            paused_until = CASE WHEN $3 THEN $4 ELSE paused_until END,
+            schedule = COALESCE($5, schedule),
+            daily_target = COALESCE($6, daily_target),
+            unit = CASE WHEN $7 THEN $8 ELSE unit END,
+            update_time = NOW()
+        WHERE id = $9
+        AND (
+            (a.group_id IS NULL AND a.user_id = $10)
+            OR EXISTS (
+                SELECT 1 FROM group_member m
+                WHERE m.group_id = a.group_id AND m.user_id = $10
+                AND (m.role IN ('owner', 'admin') OR a.user_id = $10)
+            )
+        )
+        RETURNING id, user_id, name, create_time, last_finish_time, archived, paused_until, schedule,
+            daily_target, unit, position, group_id
+        "#,
+    )
+    .bind(req.name)
+    .bind(req.archived)
+    .bind(req.paused_until.is_some())
+    .bind(req.paused_until.flatten())
+    .bind(req.schedule.map(Json))
+    .bind(req.daily_target)
+    .bind(req.unit.is_some())
+    .bind(req.unit.flatten())
+    .bind(id)
+    .bind(user_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let mut action = match action {
+        Some(action) => action,
+        None => return Ok(None),
+    };
+
+    if let Some(tags) = req.tags {
+        set_action_tags(&mut tx, user_id, action.id, &tags).await?;
+    }
+    action.tags = sqlx::query_scalar(
+        r#"
+        SELECT t.name
+        FROM action_tag atag
+        JOIN tag t ON atag.tag_id = t.id
+        WHERE atag.action_id = $1 AND t.user_id = $2
+        ORDER BY t.name
+        "#,
+    )
+    .bind(action.id)
+    .bind(user_id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Some(action))
+}
+
+/// Replaces the user's tags on an action by the given names, creating the
+/// tags the user does not have yet. Tags of other group members stay.
+async fn set_action_tags(
+    conn: &mut PgConnection,
+    user_id: i64,
+    action_id: i64,
+    names: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO tag (user_id, name, create_time)
+        SELECT $1, n.name, NOW()
+        FROM UNNEST($2::TEXT[]) AS n(name)
+        ON CONFLICT (user_id, name) DO NOTHING
+        "#,
+    )
+    .bind(user_id)
+    .bind(names)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        r#"
+        DELETE FROM action_tag
+        WHERE action_id = $1 AND tag_id IN (SELECT id FROM tag WHERE user_id = $2)
+        "#,
+    )
+    .bind(action_id)
+    .bind(user_id)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO action_tag (action_id, tag_id)
+        SELECT $1, id FROM tag
+        WHERE user_id = $2 AND name = ANY($3)
+        "#,
+    )
+    .bind(action_id)
+    .bind(user_id)
+    .bind(names)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Moves the given personal actions to the front in the given order; the
+/// user's other personal actions follow in their previous order. Nothing is
+/// changed and `GroupAction` is returned if one of the ids is a group action
+/// the user can see, or `NotFound` if another id is not one of the user's
+/// personal actions.
+pub async fn reorder_practice_actions(
+    pool: &PgPool,
+    user_id: i64,
+    ids: &[i64],
+) -> Result<ReorderOutcome, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let (owned, shared): (i64, i64) = sqlx::query_as(
+        r#"
+        SELECT
+            COUNT(*) FILTER (WHERE a.group_id IS NULL AND a.user_id = $1),
+            COUNT(*) FILTER (WHERE EXISTS (
+                SELECT 1 FROM group_member m
+                WHERE m.group_id = a.group_id AND m.user_id = $1
+            ))
+        FROM practice_action a
+        WHERE a.id = ANY($2)
+        "#,
+    )
+    .bind(user_id)
+    .bind(ids)
+    .fetch_one(&mut *tx)
+    .await?;
+    if shared > 0 {
+        return Ok(ReorderOutcome::GroupAction);
+    }
+    if owned != ids.len() as i64 {
+        return Ok(ReorderOutcome::NotFound);
+    }
+
+    sqlx::query(
+        r#"
+        WITH listed AS (
+            SELECT l.id, l.ord
+            FROM UNNEST($2::BIGINT[]) WITH ORDINALITY AS l(id, ord)
+        ),
+        ranked AS (
+            SELECT
+                a.id,
+                ROW_NUMBER() OVER (ORDER BY l.ord NULLS LAST, a.position, a.id) - 1 AS position
+            FROM practice_action a
+            LEFT JOIN listed l ON l.id = a.id
+            WHERE a.user_id = $1 AND a.group_id IS NULL
+        )
+        UPDATE practice_action a
+        SET position = r.position
+        FROM ranked r
+        WHERE a.id = r.id AND a.position <> r.position
+        "#,
+    )
+    .bind(user_id)
+    .bind(ids)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(ReorderOutcome::Reordered)
+}
+
+/// Deletes the action together with its records. Returns false if the action
+/// does not exist or the user may not change it.
+pub async fn delete_practice_action(
+    pool: &PgPool,
+    user_id: i64,
+    id: i64,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM practice_action a
+        WHERE id = $1
+        AND (
+            (a.group_id IS NULL AND a.user_id = $2)
+            OR EXISTS (
+                SELECT 1 FROM group_member m
+                WHERE m.group_id = a.group_id AND m.user_id = $2
+                AND (m.role IN ('owner', 'admin') OR a.user_id = $2)
+            )
+        )
+        "#,
+    )
+    .bind(id)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Lists the user's actions, including those of the user's groups, with
+/// statistics of the user's own completions, or only the action `action_id`
+/// when given, optionally limited to the actions tagged `tag`. Archived
+/// actions come last; without a `sort`, unfinished actions come first. Day
+/// boundaries follow the user's time zone.
 pub async fn list_actions_with_stats(
     pool: &PgPool,
     user_id: i64,
+    action_id: Option<i64>,
+    include_archived: bool,
+    tag: Option<&str>,
+    sort: Option<ActionSort>,
 ) -> Result<Vec<ActionWithStats>, sqlx::Error> {
-    let actions = sqlx::query_as::<_, ActionWithStats>(
+    let mut actions = sqlx::query_as::<_, ActionWithStats>(
         r#"
-        WITH today_completions AS (
-            SELECT action_id, true as completed
-            FROM practice_record
-            WHERE DATE(finish_time) = CURRENT_DATE
+        WITH user_tz AS (
+            SELECT (now() AT TIME ZONE time_zone)::date AS today
+            FROM users
+            WHERE id = $1
         ),
-        completion_counts AS (
-            SELECT action_id, COUNT(*) as total_count
-            FROM practice_record
-            GROUP BY action_id
+        record_stats AS (
+            SELECT
+                r.action_id,
+                COUNT(*) AS total_count,
+                COUNT(*) FILTER (WHERE r.local_day = tz.today) AS today_count,
+                COUNT(*) FILTER (WHERE r.local_day > tz.today - 7) AS last_7_count,
+                COUNT(*) FILTER (WHERE r.local_day > tz.today - 30) AS last_30_count
+            FROM practice_record r
+            CROSS JOIN user_tz tz
+            WHERE r.user_id = $1
+            GROUP BY r.action_id
         )
         SELECT 
             a.id as id,
@@ -157,124 +482,2449 @@ pub async fn list_actions_with_stats(
             a.name as name,
             a.create_time as create_time,
             a.last_finish_time as last_finish_time,
-            COALESCE(cc.total_count, 0) as total_finished,
-            COALESCE(tc.completed, false) as finished_today
+            a.archived as archived,
+            a.paused_until as paused_until,
+            a.schedule as schedule,
+            a.daily_target as daily_target,
+            a.unit as unit,
+            a.position as position,
+            a.group_id as group_id,
+            ARRAY(
+                SELECT t.name
+                FROM action_tag atag
+                JOIN tag t ON atag.tag_id = t.id
+                WHERE atag.action_id = a.id AND t.user_id = $1
+                ORDER BY t.name
+            ) as tags,
+            COALESCE(rs.total_count, 0) as total_finished,
+            COALESCE(rs.today_count, 0) as today_count,
+            COALESCE(rs.today_count, 0) >= a.daily_target as finished_today,
+            COALESCE(rs.last_7_count, 0) as last_7_days,
+            COALESCE(rs.last_30_count, 0) as last_30_days
         FROM practice_action a
-        LEFT JOIN completion_counts cc ON a.id = cc.action_id
-        LEFT JOIN today_completions tc ON a.id = tc.action_id
-        WHERE a.user_id = $1
-        ORDER BY finished_today ASC, last_finish_time DESC NULLS LAST, create_time DESC
+        LEFT JOIN record_stats rs ON a.id = rs.action_id
+        WHERE (
+            (a.group_id IS NULL AND a.user_id = $1)
+            OR EXISTS (
+                SELECT 1 FROM group_member m
+                WHERE m.group_id = a.group_id AND m.user_id = $1
+            )
+        )
+        AND ($2::BIGINT IS NULL OR a.id = $2)
+        AND ($3 OR NOT a.archived)
+        AND ($4::TEXT IS NULL OR EXISTS (
+            SELECT 1
+            FROM action_tag atag
+            JOIN tag t ON atag.tag_id = t.id
+            WHERE atag.action_id = a.id AND t.user_id = $1 AND t.name = $4
+        ))
+        ORDER BY
+            archived ASC,
+            CASE WHEN $5 IN ('manual', 'streak') THEN a.group_id IS NOT NULL END ASC,
+            CASE WHEN $5 IN ('manual', 'streak') AND a.group_id IS NULL THEN a.position END ASC,
+            CASE WHEN $5 IN ('manual', 'streak') THEN a.id END ASC,
+            CASE WHEN $5 = 'alphabetical' THEN LOWER(a.name) END ASC,
+            CASE WHEN $5 IS NULL THEN COALESCE(rs.today_count, 0) >= a.daily_target END ASC,
+            last_finish_time DESC NULLS LAST,
+            create_time DESC
         "#,
     )
     .bind(user_id)
+    .bind(action_id)
+    .bind(include_archived)
+    .bind(tag)
+    .bind(sort.map(|sort| sort.as_sql()))
     .fetch_all(pool)
     .await?;
 
+    let days_by_action = get_completion_days(pool, user_id, action_id).await?;
+    let today = local_today(pool, user_id).await?;
+
+    for action in &mut actions {
+        let days = days_by_action
+            .get(&action.id)
+            .map(Vec::as_slice)
+            .unwrap_or(&[]);
+        let status = action.schedule.evaluate(days, today);
+        let paused = action.paused_until.is_some_and(|until| until >= today);
+        action.current_streak = status.streaks.current;
+        action.longest_streak = status.streaks.longest;
+        action.due = status.due && !action.archived && !paused;
+        action.done_for_period = status.done_for_period;
+    }
+
+    // Ties keep the manual order
+    if let Some(ActionSort::Streak) = sort {
+        actions.sort_by_key(|a| {
+            (
+                a.archived,
+                Reverse(a.current_streak),
+                Reverse(a.longest_streak),
+            )
+        });
+    }
+
     Ok(actions)
 }
 
-pub async fn get_practice_records(
-    pool: &PgPool,
-    user_id: i64,
-    action_id: i64,
-) -> Result<Vec<PracticeRecord>, sqlx::Error> {
-    let records = sqlx::query_as::<_, PracticeRecord>(
+pub async fn create_user_tag(pool: &PgPool, user_id: i64, name: &str) -> Result<Tag, sqlx::Error> {
+    let tag = sqlx::query_as::<_, Tag>(
         r#"
-        SELECT r.id, r.action_id, r.finish_time, r.note
-        FROM practice_record r
-        JOIN practice_action a ON r.action_id = a.id
-        WHERE r.action_id = $1 AND a.user_id = $2
-        ORDER BY r.finish_time DESC
+        INSERT INTO tag (user_id, name, create_time)
+        VALUES ($1, $2, NOW())
+        RETURNING id, name, create_time
         "#,
     )
-    .bind(action_id)
     .bind(user_id)
-    .fetch_all(pool)
+    .bind(name)
+    .fetch_one(pool)
     .await?;
 
-    Ok(records)
+    Ok(tag)
 }
 
-pub async fn can_finish_today(
+pub async fn rename_user_tag(
     pool: &PgPool,
     user_id: i64,
-    action_id: i64,
-) -> Result<bool, sqlx::Error> {
-    let offset = time::UtcOffset::from_hms(8, 0, 0).unwrap();
-    let today = OffsetDateTime::now_utc().to_offset(offset).date();
+    id: i64,
+    name: &str,
+) -> Result<Option<Tag>, sqlx::Error> {
+    let tag = sqlx::query_as::<_, Tag>(
+        r#"
+        UPDATE tag
+        SET name = $1
+        WHERE id = $2 AND user_id = $3
+        RETURNING id, name, create_time
+        "#,
+    )
+    .bind(name)
+    .bind(id)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(tag)
+}
 
-    let count: Option<i64> = sqlx::query_scalar(
+/// Deletes the tag and detaches it from its actions. Returns false if the tag
+/// does not exist or belongs to another user.
+pub async fn delete_user_tag(pool: &PgPool, user_id: i64, id: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
         r#"
-        SELECT COUNT(*)
-        FROM practice_record r
-        JOIN practice_action a ON r.action_id = a.id
-        WHERE r.action_id = $1 
-        AND a.user_id = $2
-        AND DATE(r.finish_time AT TIME ZONE 'UTC+8') = $3
+        DELETE FROM tag
+        WHERE id = $1 AND user_id = $2
         "#,
     )
-    .bind(action_id)
+    .bind(id)
     .bind(user_id)
-    .bind(today)
-    .fetch_one(pool)
+    .execute(pool)
     .await?;
 
-    Ok(count.unwrap_or(0) == 0)
+    Ok(result.rows_affected() > 0)
 }
 
-pub async fn create_practice_record(
+/// Lists the user's tags, or only the tag `tag_id` when given, with the
+/// statistics of their non-archived actions added up.
+pub async fn list_tags_with_stats(
     pool: &PgPool,
     user_id: i64,
-    action_id: i64,
-    note: Option<String>,
-) -> Result<PracticeRecord, sqlx::Error> {
-    let now = OffsetDateTime::now_utc();
-
-    // Verify user owns the action
-    let action_exists: Option<bool> = sqlx::query_scalar(
+    tag_id: Option<i64>,
+) -> Result<Vec<TagWithStats>, sqlx::Error> {
+    let tags = sqlx::query_as::<_, TagWithStats>(
         r#"
-        SELECT EXISTS(
-            SELECT 1 FROM practice_action
-            WHERE id = $1 AND user_id = $2
+        WITH user_tz AS (
+            SELECT (now() AT TIME ZONE time_zone)::date AS today
+            FROM users
+            WHERE id = $1
+        ),
+        action_stats AS (
+            SELECT
+                a.id,
+                a.daily_target,
+                COUNT(r.id) AS total_count,
+                COUNT(r.id) FILTER (WHERE r.local_day = tz.today) AS today_count,
+                COUNT(r.id) FILTER (WHERE r.local_day > tz.today - 7) AS last_7_count,
+                COUNT(r.id) FILTER (WHERE r.local_day > tz.today - 30) AS last_30_count
+            FROM practice_action a
+            LEFT JOIN practice_record r ON r.action_id = a.id AND r.user_id = $1
+            CROSS JOIN user_tz tz
+            WHERE NOT a.archived
+            AND (
+                (a.group_id IS NULL AND a.user_id = $1)
+                OR EXISTS (
+                    SELECT 1 FROM group_member m
+                    WHERE m.group_id = a.group_id AND m.user_id = $1
+                )
+            )
+            GROUP BY a.id
         )
+        SELECT
+            t.id,
+            t.name,
+            t.create_time,
+            COUNT(s.id) AS action_count,
+            COUNT(s.id) FILTER (WHERE s.today_count >= s.daily_target) AS finished_today,
+            COALESCE(SUM(s.total_count), 0)::BIGINT AS total_finished,
+            COALESCE(SUM(s.last_7_count), 0)::BIGINT AS last_7_days,
+            COALESCE(SUM(s.last_30_count), 0)::BIGINT AS last_30_days
+        FROM tag t
+        LEFT JOIN action_tag atag ON atag.tag_id = t.id
+        LEFT JOIN action_stats s ON s.id = atag.action_id
+        WHERE t.user_id = $1
+        AND ($2::BIGINT IS NULL OR t.id = $2)
+        GROUP BY t.id
+        ORDER BY t.name
         "#,
     )
-    .bind(action_id)
     .bind(user_id)
-    .fetch_one(pool)
+    .bind(tag_id)
+    .fetch_all(pool)
     .await?;
 
-    if !action_exists.unwrap_or(false) {
-        return Err(sqlx::Error::RowNotFound);
-    }
+    Ok(tags)
+}
+
+/// Creates a group with the user as its owner.
+pub async fn create_practice_group(
+    pool: &PgPool,
+    user_id: i64,
+    name: &str,
+) -> Result<Group, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let group_id: i64 = sqlx::query_scalar(
+        r#"
+        INSERT INTO practice_group (name, create_time)
+        VALUES ($1, NOW())
+        RETURNING id
+        "#,
+    )
+    .bind(name)
+    .fetch_one(&mut *tx)
+    .await?;
 
-    // Update last_finish_time
     sqlx::query(
         r#"
-        UPDATE practice_action 
-        SET last_finish_time = $1
-        WHERE id = $2 AND user_id = $3
+        INSERT INTO group_member (group_id, user_id, role, join_time)
+        VALUES ($1, $2, $3, NOW())
         "#,
     )
-    .bind(now)
-    .bind(action_id)
+    .bind(group_id)
     .bind(user_id)
-    .execute(pool)
+    .bind(GroupRole::Owner.as_sql())
+    .execute(&mut *tx)
     .await?;
 
-    // Create record
-    let record = sqlx::query_as::<_, PracticeRecord>(
+    tx.commit().await?;
+
+    let group = list_user_groups(pool, user_id, Some(group_id)).await?.pop();
+    Ok(group.expect("group was just created"))
+}
+
+/// Lists the groups the user belongs to, or only the group `group_id` when
+/// given, with the user's role in each.
+pub async fn list_user_groups(
+    pool: &PgPool,
+    user_id: i64,
+    group_id: Option<i64>,
+) -> Result<Vec<Group>, sqlx::Error> {
+    let groups = sqlx::query_as::<_, Group>(
         r#"
-        INSERT INTO practice_record (action_id, finish_time, note)
-        VALUES ($1, $2, $3)
-        RETURNING id, action_id, finish_time, note
+        SELECT
+            g.id,
+            g.name,
+            g.create_time,
+            m.role,
+            (SELECT COUNT(*) FROM group_member c WHERE c.group_id = g.id) AS member_count
+        FROM practice_group g
+        JOIN group_member m ON m.group_id = g.id
+        WHERE m.user_id = $1
+        AND ($2::BIGINT IS NULL OR g.id = $2)
+        ORDER BY g.name, g.id
         "#,
     )
-    .bind(action_id)
-    .bind(now)
-    .bind(note)
-    .fetch_one(pool)
+    .bind(user_id)
+    .bind(group_id)
+    .fetch_all(pool)
     .await?;
 
-    Ok(record)
+    Ok(groups)
+}
+
+/// The user's role in the group, or `None` if the user is not a member.
+pub async fn get_group_role(
+    pool: &PgPool,
+    user_id: i64,
+    group_id: i64,
+) -> Result<Option<GroupRole>, sqlx::Error> {
+    let role: Option<String> = sqlx::query_scalar(
+        r#"
+        SELECT role FROM group_member
+        WHERE group_id = $1 AND user_id = $2
+        "#,
+    )
+    .bind(group_id)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(role.as_deref().and_then(GroupRole::from_sql))
+}
+
+pub async fn rename_practice_group(
+    pool: &PgPool,
+    group_id: i64,
+    name: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE practice_group
+        SET name = $1
+        WHERE id = $2
+        "#,
+    )
+    .bind(name)
+    .bind(group_id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Deletes the group. Its actions stay with the members who created them.
+pub async fn delete_practice_group(pool: &PgPool, group_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        DELETE FROM practice_group
+        WHERE id = $1
+        "#,
+    )
+    .bind(group_id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Members with the owner first, then admins, then by name.
+pub async fn get_group_members(
+    pool: &PgPool,
+    group_id: i64,
+) -> Result<Vec<GroupMember>, sqlx::Error> {
+    let members = sqlx::query_as::<_, GroupMember>(
+        r#"
+        SELECT m.user_id, u.username, m.role, m.join_time
+        FROM group_member m
+        JOIN users u ON m.user_id = u.id
+        WHERE m.group_id = $1
+        ORDER BY
+            CASE m.role WHEN 'owner' THEN 0 WHEN 'admin' THEN 1 ELSE 2 END,
+            u.username
+        "#,
+    )
+    .bind(group_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(members)
+}
+
+/// Adds the user to the group. Fails with a unique violation if the user
+/// already is a member.
+pub async fn add_group_member(
+    pool: &PgPool,
+    group_id: i64,
+    user_id: i64,
+    role: GroupRole,
+) -> Result<GroupMember, sqlx::Error> {
+    let member = sqlx::query_as::<_, GroupMember>(
+        r#"
+        INSERT INTO group_member (group_id, user_id, role, join_time)
+        SELECT $1, id, $3, NOW() FROM users WHERE id = $2
+        RETURNING user_id, (SELECT username FROM users WHERE id = $2), role, join_time
+        "#,
+    )
+    .bind(group_id)
+    .bind(user_id)
+    .bind(role.as_sql())
+    .fetch_one(pool)
+    .await?;
+
+    Ok(member)
+}
+
+/// Changes a member's role. The owner's role cannot be changed, so this
+/// returns `None` for the owner as well as for non-members.
+pub async fn update_group_member_role(
+    pool: &PgPool,
+    group_id: i64,
+    user_id: i64,
+    role: GroupRole,
+) -> Result<Option<GroupMember>, sqlx::Error> {
+    let member = sqlx::query_as::<_, GroupMember>(
+        r#"
+        UPDATE group_member m
+        SET role = $3
+        FROM users u
+        WHERE m.group_id = $1 AND m.user_id = $2 AND m.user_id = u.id
+        AND m.role <> 'owner'
+        RETURNING m.user_id, u.username, m.role, m.join_time
+        "#,
+    )
+    .bind(group_id)
+    .bind(user_id)
+    .bind(role.as_sql())
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(member)
+}
+
+/// Removes a member other than the owner, together with the member's
+/// reminders on the group's actions. Their records stay and still count on
+/// the leaderboard while they are a member again.
+pub async fn remove_group_member(
+    pool: &PgPool,
+    group_id: i64,
+    user_id: i64,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query(
+        r#"
+        DELETE FROM group_member
+        WHERE group_id = $1 AND user_id = $2 AND role <> 'owner'
+        "#,
+    )
+    .bind(group_id)
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    sqlx::query(
+        r#"
+        DELETE FROM reminder rm
+        USING practice_action a
+        WHERE rm.action_id = a.id AND a.group_id = $1 AND rm.user_id = $2
+        AND a.user_id <> $2
+        "#,
+    )
+    .bind(group_id)
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+/// Ranks the group's members by the days on which they met the daily target
+/// of the group's actions between `from` and `to`, then by completions. Each
+/// member's days follow their own time zone.
+pub async fn get_leaderboard_entries(
+    pool: &PgPool,
+    group_id: i64,
+    from: Date,
+    to: Date,
+) -> Result<Vec<LeaderboardEntry>, sqlx::Error> {
+    let entries = sqlx::query_as::<_, LeaderboardEntry>(
+        r#"
+        WITH members AS (
+            SELECT m.user_id, u.username
+            FROM group_member m
+            JOIN users u ON m.user_id = u.id
+            WHERE m.group_id = $1
+        ),
+        daily AS (
+            SELECT
+                r.user_id,
+                COUNT(*) AS count,
+                COUNT(*) >= a.daily_target AS met
+            FROM practice_record r
+            JOIN practice_action a ON r.action_id = a.id
+            JOIN members mb ON r.user_id = mb.user_id
+            WHERE a.group_id = $1
+            AND r.local_day BETWEEN $2 AND $3
+            GROUP BY r.user_id, r.action_id, r.local_day, a.daily_target
+        ),
+        totals AS (
+            SELECT
+                mb.user_id,
+                mb.username,
+                COALESCE(SUM(d.count), 0)::BIGINT AS completions,
+                COUNT(*) FILTER (WHERE d.met) AS days_met
+            FROM members mb
+            LEFT JOIN daily d ON d.user_id = mb.user_id
+            GROUP BY mb.user_id, mb.username
+        )
+        SELECT
+            RANK() OVER (ORDER BY days_met DESC, completions DESC) AS rank,
+            user_id,
+            username,
+            completions,
+            days_met
+        FROM totals
+        ORDER BY rank, username
+        "#,
+    )
+    .bind(group_id)
+    .bind(from)
+    .bind(to)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(entries)
+}
+
+pub async fn get_reminders(
+    pool: &PgPool,
+    user_id: i64,
+    action_id: i64,
+) -> Result<Vec<Reminder>, sqlx::Error> {
+    let reminders = sqlx::query_as::<_, Reminder>(
+        r#"
+        SELECT id, action_id, remind_at, last_sent_date
+        FROM reminder
+        WHERE action_id = $1 AND user_id = $2
+        ORDER BY remind_at
+        "#,
+    )
+    .bind(action_id)
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(reminders)
+}
+
+/// The caller is expected to have checked that the user can see the action.
+pub async fn create_reminder(
+    pool: &PgPool,
+    user_id: i64,
+    action_id: i64,
+    remind_at: Time,
+) -> Result<Reminder, sqlx::Error> {
+    let reminder = sqlx::query_as::<_, Reminder>(
+        r#"
+        INSERT INTO reminder (action_id, user_id, remind_at, create_time)
+        VALUES ($1, $2, $3, NOW())
+        RETURNING id, action_id, remind_at, last_sent_date
+        "#,
+    )
+    .bind(action_id)
+    .bind(user_id)
+    .bind(remind_at)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(reminder)
+}
+
+/// Returns false if the user has no such reminder.
+pub async fn delete_reminder(
+    pool: &PgPool,
+    user_id: i64,
+    reminder_id: i64,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM reminder
+        WHERE id = $1 AND user_id = $2
+        "#,
+    )
+    .bind(reminder_id)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Marks every reminder whose local time has passed today, that has not fired
+/// yet today and whose retry is not still waiting as sent, and returns them.
+/// Reminders locked by a concurrent claim are skipped. A reminder that could
+/// not be sent is handed back with `release_reminder`.
+pub async fn claim_due_reminders(pool: &PgPool) -> Result<Vec<DueReminder>, sqlx::Error> {
+    let reminders = sqlx::query_as::<_, DueReminder>(
+        r#"
+        WITH due AS (
+            SELECT
+                rm.id, rm.last_sent_date AS previous_sent_date,
+                (now() AT TIME ZONE u.time_zone)::date AS local_date
+            FROM reminder rm
+            JOIN practice_action a ON rm.action_id = a.id
+            JOIN users u ON rm.user_id = u.id
+            WHERE NOT a.archived
+            AND (now() AT TIME ZONE u.time_zone)::time >= rm.remind_at
+            AND (
+                rm.last_sent_date IS NULL
+                OR rm.last_sent_date < (now() AT TIME ZONE u.time_zone)::date
+            )
+            AND (rm.retry_time IS NULL OR rm.retry_time <= now())
+            FOR UPDATE OF rm SKIP LOCKED
+        )
+        UPDATE reminder rm
+        SET last_sent_date = due.local_date,
+            -- Only a retry continues the count of failed sends
+            attempts = CASE WHEN rm.retry_time IS NULL THEN 0 ELSE rm.attempts END,
+            retry_time = NULL
+        FROM due, practice_action a, users u
+        WHERE rm.id = due.id AND rm.action_id = a.id AND rm.user_id = u.id
+        RETURNING
+            rm.id, rm.action_id, a.name AS action_name, u.id AS user_id, u.username, u.email,
+            rm.remind_at, rm.attempts, due.previous_sent_date
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(reminders)
+}
+
+/// Undoes `claim_due_reminders` for one reminder after its `attempts`-th
+/// failed send, so that the first check from `retry_time` on sends it again.
+pub async fn release_reminder(
+    pool: &PgPool,
+    id: i64,
+    previous_sent_date: Option<Date>,
+    attempts: i32,
+    retry_time: OffsetDateTime,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE reminder
+        SET last_sent_date = $2, attempts = $3, retry_time = $4
+        WHERE id = $1
+        "#,
+    )
+    .bind(id)
+    .bind(previous_sent_date)
+    .bind(attempts)
+    .bind(retry_time)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// The local days on which the user's own completions of each action, or only
+/// of `action_id`, reached its daily target, ascending.
+///
+/// Streaks only need these days, which keeps the rows fetched bounded by the
+/// age of the action rather than its record count.
+pub async fn get_completion_days(
+    pool: &PgPool,
+    user_id: i64,
+    action_id: Option<i64>,
+) -> Result<HashMap<i64, Vec<Date>>, sqlx::Error> {
+    let completion_days: Vec<(i64, Date)> = sqlx::query_as(
+        r#"
+        SELECT r.action_id, r.local_day AS day
+        FROM practice_record r
+        JOIN practice_action a ON r.action_id = a.id
+        WHERE r.user_id = $1
+        AND ($2::BIGINT IS NULL OR a.id = $2)
+        GROUP BY r.action_id, day, a.daily_target
+        HAVING COUNT(*) >= a.daily_target
+        ORDER BY r.action_id, day
+        "#,
+    )
+    .bind(user_id)
+    .bind(action_id)
+    .fetch_all(pool)
+    .await?;
+
+    let mut days_by_action: HashMap<i64, Vec<Date>> = HashMap::new();
+    for (action_id, day) in completion_days {
+        days_by_action.entry(action_id).or_default().push(day);
+    }
+
+    Ok(days_by_action)
+}
+
+/// The current calendar date in the user's time zone.
+pub async fn local_today(pool: &PgPool, user_id: i64) -> Result<Date, sqlx::Error> {
+    let today: Date = sqlx::query_scalar(
+        r#"
+        SELECT (now() AT TIME ZONE time_zone)::date
+        FROM users
+        WHERE id = $1
+        "#,
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(today)
+}
+
+/// Returns up to `limit` records of the action, newest first, starting after
+/// `cursor` and optionally restricted to local days between `from` and `to`.
+/// For group actions these include the records of every member.
+pub async fn get_practice_records(
+    pool: &PgPool,
+    user_id: i64,
+    action_id: i64,
+    cursor: Option<RecordCursor>,
+    from: Option<Date>,
+    to: Option<Date>,
+    limit: i64,
+) -> Result<Vec<PracticeRecord>, sqlx::Error> {
+    let records = sqlx::query_as::<_, PracticeRecord>(
+        r#"
+        SELECT r.id, r.action_id, r.user_id, r.finish_time, r.note, r.quantity, r.duration_seconds
+        FROM practice_record r
+        JOIN practice_action a ON r.action_id = a.id
+        WHERE r.action_id = $1
+        AND (
+            (a.group_id IS NULL AND a.user_id = $2)
+            OR EXISTS (
+                SELECT 1 FROM group_member m
+                WHERE m.group_id = a.group_id AND m.user_id = $2
+            )
+        )
+        AND ($3::TIMESTAMPTZ IS NULL OR (r.finish_time, r.id) < ($3, $4))
+        AND ($5::DATE IS NULL OR r.local_day >= $5)
+        AND ($6::DATE IS NULL OR r.local_day <= $6)
+        ORDER BY r.finish_time DESC, r.id DESC
+        LIMIT $7
+        "#,
+    )
+    .bind(action_id)
+    .bind(user_id)
+    .bind(cursor.map(|c| c.finish_time))
+    .bind(cursor.map(|c| c.id))
+    .bind(from)
+    .bind(to)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records)
+}
+
+/// Counts the user's completions per local day between `from` and `to`
+/// (inclusive).
+pub async fn get_practice_calendar(
+    pool: &PgPool,
+    user_id: i64,
+    action_id: i64,
+    from: Date,
+    to: Date,
+) -> Result<Vec<CalendarDay>, sqlx::Error> {
+    let days = sqlx::query_as::<_, CalendarDay>(
+        r#"
+        SELECT
+            r.local_day AS date,
+            COUNT(*) AS count,
+            COUNT(*) >= a.daily_target AS done
+        FROM practice_record r
+        JOIN practice_action a ON r.action_id = a.id
+        WHERE r.action_id = $1
+        AND r.user_id = $2
+        AND r.local_day BETWEEN $3 AND $4
+        GROUP BY date, a.daily_target
+        ORDER BY date
+        "#,
+    )
+    .bind(action_id)
+    .bind(user_id)
+    .bind(from)
+    .bind(to)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(days)
+}
+
+/// Counts the user's completions per day, week or month between `from` and
+/// `to`, including empty periods.
+pub async fn get_completion_counts(
+    pool: &PgPool,
+    user_id: i64,
+    from: Date,
+    to: Date,
+    granularity: Granularity,
+) -> Result<Vec<PeriodCount>, sqlx::Error> {
+    let counts = sqlx::query_as::<_, PeriodCount>(
+        r#"
+        WITH periods AS (
+            SELECT generate_series(
+                date_trunc($4, $2::date::timestamp),
+                date_trunc($4, $3::date::timestamp),
+                ('1 ' || $4)::interval
+            )::date AS period
+        ),
+        counts AS (
+            SELECT
+                    date_trunc($4, r.local_day::timestamp)::date AS period,
+                COUNT(*) AS count
+            FROM practice_record r
+            WHERE r.user_id = $1
+            AND r.local_day BETWEEN $2 AND $3
+            GROUP BY 1
+        )
+        SELECT p.period, COALESCE(c.count, 0) AS count
+        FROM periods p
+        LEFT JOIN counts c ON c.period = p.period
+        ORDER BY p.period
+        "#,
+    )
+    .bind(user_id)
+    .bind(from)
+    .bind(to)
+    .bind(granularity.as_sql())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(counts)
+}
+
+/// Rates every non-archived action by the share of its scheduled completions
+/// it met between `from` and `to`, best first. Days before an action was
+/// created are not held against it.
+pub async fn get_action_performance(
+    pool: &PgPool,
+    user_id: i64,
+    from: Date,
+    to: Date,
+) -> Result<Vec<ActionPerformance>, sqlx::Error> {
+    let performance = sqlx::query_as::<_, ActionPerformance>(
+        r#"
+        WITH tz AS (
+            SELECT time_zone FROM users WHERE id = $1
+        ),
+        actions AS (
+            SELECT
+                a.id, a.name, a.schedule, a.daily_target,
+                GREATEST($2::date, (a.create_time AT TIME ZONE tz.time_zone)::date) AS start_day
+            FROM practice_action a
+            CROSS JOIN tz
+            WHERE NOT a.archived
+            AND (
+                (a.group_id IS NULL AND a.user_id = $1)
+                OR EXISTS (
+                    SELECT 1 FROM group_member m
+                    WHERE m.group_id = a.group_id AND m.user_id = $1
+                )
+            )
+        ),
+        done AS (
+            SELECT d.action_id, COUNT(*) AS done_days
+            FROM (
+                SELECT r.action_id, r.local_day AS day
+                FROM practice_record r
+                JOIN actions a ON r.action_id = a.id
+                WHERE r.user_id = $1
+                AND r.local_day BETWEEN $2 AND $3
+                GROUP BY r.action_id, day, a.daily_target
+                HAVING COUNT(*) >= a.daily_target
+            ) d
+            GROUP BY d.action_id
+        ),
+        expected AS (
+            SELECT a.id, CASE a.schedule->>'type'
+                WHEN 'every_n_days' THEN
+                    CEIL(($3::date - a.start_day + 1)::numeric / (a.schedule->>'interval')::int)
+                WHEN 'times_per_week' THEN
+                    CEIL(($3::date - a.start_day + 1)::numeric * (a.schedule->>'times')::int / 7)
+                WHEN 'weekdays' THEN (
+                    SELECT COUNT(*)
+                    FROM generate_series(a.start_day, $3::date, interval '1 day') g(day)
+                    WHERE EXTRACT(ISODOW FROM g.day)::text
+                        IN (SELECT jsonb_array_elements_text(a.schedule->'days'))
+                )
+                ELSE $3::date - a.start_day + 1
+            END AS expected
+            FROM actions a
+            WHERE a.start_day <= $3::date
+        )
+        SELECT
+            a.id,
+            a.name,
+            COALESCE(d.done_days, 0) AS done_days,
+            e.expected::BIGINT AS expected,
+            LEAST(COALESCE(d.done_days, 0)::float8 / NULLIF(e.expected, 0)::float8, 1.0)
+                AS completion_rate
+        FROM actions a
+        JOIN expected e ON e.id = a.id
+        LEFT JOIN done d ON d.action_id = a.id
+        ORDER BY completion_rate DESC NULLS LAST, a.name
+        "#,
+    )
+    .bind(user_id)
+    .bind(from)
+    .bind(to)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(performance)
+}
+
+/// Local days on which the user completed anything, ascending.
+pub async fn get_active_days(pool: &PgPool, user_id: i64) -> Result<Vec<Date>, sqlx::Error> {
+    let days: Vec<Date> = sqlx::query_scalar(
+        r#"
+        SELECT DISTINCT r.local_day AS day
+        FROM practice_record r
+        WHERE r.user_id = $1
+        ORDER BY day
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(days)
+}
+
+/// Number of local calendar days between `time` and now in the user's zone,
+/// e.g. 0 for today and 1 for yesterday.
+pub async fn local_days_ago(
+    pool: &PgPool,
+    user_id: i64,
+    time: OffsetDateTime,
+) -> Result<i32, sqlx::Error> {
+    let days: i32 = sqlx::query_scalar(
+        r#"
+        SELECT (now() AT TIME ZONE time_zone)::date - ($2 AT TIME ZONE time_zone)::date
+        FROM users
+        WHERE id = $1
+        "#,
+    )
+    .bind(user_id)
+    .bind(time)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(days)
+}
+
+/// Why a completion may not be recorded at `finish_time`, if it may not: the
+/// time is in the future, or on a local day more than `backfill_days` ago.
+pub async fn check_finish_time(
+    pool: &PgPool,
+    user_id: i64,
+    finish_time: OffsetDateTime,
+    backfill_days: u32,
+) -> Result<Option<String>, sqlx::Error> {
+    if finish_time > OffsetDateTime::now_utc() {
+        return Ok(Some("Finish time is in the future".to_string()));
+    }
+    let days_ago = local_days_ago(pool, user_id, finish_time).await?;
+    if days_ago > 0 && backfill_days == 0 {
+        return Ok(Some("Finish time is before today".to_string()));
+    }
+    if i64::from(days_ago) > i64::from(backfill_days) {
+        return Ok(Some(format!(
+            "Finish time is more than {} days ago",
+            backfill_days
+        )));
+    }
+    Ok(None)
+}
+
+/// Records a completion in one transaction, unless the action is archived or
+/// its daily target is already reached on the user's local day of the
+/// finish time. `synced` is the client id and modification time of a
+/// completion made by an offline client.
+pub async fn create_practice_record(
+    pool: &PgPool,
+    user_id: i64,
+    action_id: i64,
+    req: FinishActionRequest,
+    synced: Option<(Uuid, OffsetDateTime)>,
+) -> Result<FinishOutcome, sqlx::Error> {
+    let finish_time = req.finish_time.unwrap_or_else(OffsetDateTime::now_utc);
+
+    let mut tx = pool.begin().await?;
+
+    // Lock the action so that finishes of it queue up here, which also makes
+    // them see each other's records when picking a slot below
+    let action: Option<(String, bool)> = sqlx::query_as(
+        r#"
+        SELECT name, archived
+        FROM practice_action a
+        WHERE id = $1
+        AND (
+            (a.group_id IS NULL AND a.user_id = $2)
+            OR EXISTS (
+                SELECT 1 FROM group_member m
+                WHERE m.group_id = a.group_id AND m.user_id = $2
+            )
+        )
+        FOR UPDATE
+        "#,
+    )
+    .bind(action_id)
+    .bind(user_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let action_name = match action {
+        None => return Ok(FinishOutcome::NotFound),
+        Some((_, true)) => return Ok(FinishOutcome::Archived),
+        Some((name, false)) => name,
+    };
+
+    // Take the lowest free slot of the local day. Nothing is inserted once
+    // all daily_target slots are taken, and the unique index on the slot
+    // rejects whatever still slips past the lock.
+    let record = sqlx::query_as::<_, PracticeRecord>(
+        r#"
+        INSERT INTO practice_record (
+            action_id, user_id, finish_time, note, quantity, duration_seconds, local_day, day_slot,
+            client_id, update_time
+        )
+        SELECT
+            $1, $2, $3, $4, $5, $6, d.local_day, free.slot,
+            COALESCE($7, gen_random_uuid()), COALESCE($8, NOW())
+        FROM practice_action a
+        JOIN users u ON u.id = $2
+        CROSS JOIN LATERAL (SELECT ($3 AT TIME ZONE u.time_zone)::date AS local_day) d
+        CROSS JOIN LATERAL (
+            SELECT MIN(s) AS slot
+            FROM generate_series(1, a.daily_target) s
+            WHERE NOT EXISTS (
+                SELECT 1 FROM practice_record r
+                WHERE r.action_id = a.id AND r.user_id = $2
+                AND r.local_day = d.local_day AND r.day_slot = s
+            )
+        ) free
+        WHERE a.id = $1 AND free.slot IS NOT NULL
+        RETURNING id, action_id, user_id, finish_time, note, quantity, duration_seconds
+        "#,
+    )
+    .bind(action_id)
+    .bind(user_id)
+    .bind(finish_time)
+    .bind(req.note)
+    .bind(req.quantity)
+    .bind(req.duration_seconds)
+    .bind(synced.map(|(client_id, _)| client_id))
+    .bind(synced.map(|(_, modified_time)| modified_time))
+    .fetch_optional(&mut *tx)
+    .await;
+    let record = match record {
+        Ok(Some(record)) => record,
+        Ok(None) => return Ok(FinishOutcome::TargetReached),
+        Err(sqlx::Error::Database(e)) if e.constraint() == Some("practice_record_day_slot_key") => {
+            return Ok(FinishOutcome::TargetReached)
+        }
+        Err(e) => return Err(e),
+    };
+
+    // last_finish_time never moves back for backfilled records
+    sqlx::query(
+        r#"
+        UPDATE practice_action
+        SET last_finish_time = GREATEST(last_finish_time, $1)
+        WHERE id = $2
+        "#,
+    )
+    .bind(finish_time)
+    .bind(action_id)
+    .execute(&mut *tx)
+    .await?;
+
+    enqueue_webhook_event(
+        &mut tx,
+        user_id,
+        WebhookEvent::RecordCreated,
+        &json!({ "action": { "id": action_id, "name": action_name }, "record": &record }),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(FinishOutcome::Finished(record))
+}
+
+/// Applies the given changes to one of the user's own records; fields left as
+/// `None` keep their current value.
+pub async fn update_practice_record(
+    pool: &PgPool,
+    user_id: i64,
+    record_id: i64,
+    req: UpdateRecordRequest,
+) -> Result<Option<PracticeRecord>, sqlx::Error> {
+    let record = sqlx::query_as::<_, PracticeRecord>(
+        r#"
+        UPDATE practice_record r
+        SET note = COALESCE($1, r.note),
+            quantity = COALESCE($2, r.quantity),
+            duration_seconds = COALESCE($3, r.duration_seconds),
+            update_time = NOW()
+        WHERE r.id = $4 AND r.user_id = $5
+        RETURNING r.id, r.action_id, r.user_id, r.finish_time, r.note, r.quantity, r.duration_seconds
+        "#,
+    )
+    .bind(req.note)
+    .bind(req.quantity)
+    .bind(req.duration_seconds)
+    .bind(record_id)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record)
+}
+
+/// Removes one of the user's records and moves the action's `last_finish_time`
+/// back to the latest remaining record. Returns false if the user has no such
+/// record.
+pub async fn delete_practice_record(
+    pool: &PgPool,
+    user_id: i64,
+    record_id: i64,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let action_id: Option<i64> = sqlx::query_scalar(
+        r#"
+        DELETE FROM practice_record
+        WHERE id = $1 AND user_id = $2
+        RETURNING action_id
+        "#,
+    )
+    .bind(record_id)
+    .bind(user_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let action_id = match action_id {
+        Some(action_id) => action_id,
+        None => return Ok(false),
+    };
+
+    sqlx::query(
+        r#"
+        UPDATE practice_action
+        SET last_finish_time = (
+            SELECT MAX(finish_time) FROM practice_record
+            WHERE action_id = $1
+        )
+        WHERE id = $1
+        "#,
+    )
+    .bind(action_id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+/// The user's own actions outside of groups, which imports match by name.
+/// They stay locked until the import ends, so that no completion of them
+/// interferes.
+pub async fn get_import_targets(
+    conn: &mut PgConnection,
+    user_id: i64,
+) -> Result<Vec<ImportTarget>, sqlx::Error> {
+    let targets = sqlx::query_as::<_, ImportTarget>(
+        r#"
+        SELECT id, name, daily_target
+        FROM practice_action
+        WHERE user_id = $1 AND group_id IS NULL
+        ORDER BY id
+        FOR UPDATE
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(conn)
+    .await?;
+
+    Ok(targets)
+}
+
+/// Creates an action for imported records, appended to the manual order.
+pub async fn create_imported_action(
+    conn: &mut PgConnection,
+    user_id: i64,
+    action: &ImportAction,
+) -> Result<i64, sqlx::Error> {
+    let id: i64 = sqlx::query_scalar(
+        r#"
+        INSERT INTO practice_action (
+            user_id, name, create_time, archived, schedule, daily_target, unit, position
+        )
+        VALUES (
+            $1, $2, $3, $4, $5, $6, $7,
+            (
+                SELECT COALESCE(MAX(position) + 1, 0) FROM practice_action
+                WHERE user_id = $1 AND group_id IS NULL
+            )
+        )
+        RETURNING id
+        "#,
+    )
+    .bind(user_id)
+    .bind(&action.name)
+    .bind(action.create_time)
+    .bind(action.archived)
+    .bind(Json(&action.schedule))
+    .bind(action.daily_target)
+    .bind(&action.unit)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if !action.tags.is_empty() {
+        set_action_tags(conn, user_id, id, &action.tags).await?;
+    }
+
+    Ok(id)
+}
+
+/// Resolves the finish times of imported rows, given either as a time or as
+/// a local day, which is placed at noon. Returns each finish time with its
+/// local day, in the order given.
+pub async fn resolve_import_times(
+    conn: &mut PgConnection,
+    user_id: i64,
+    times: &[Option<OffsetDateTime>],
+    days: &[Option<Date>],
+) -> Result<Vec<(OffsetDateTime, Date)>, sqlx::Error> {
+    let resolved = sqlx::query_as::<_, (OffsetDateTime, Date)>(
+        r#"
+        WITH rows AS (
+            SELECT
+                i.ord,
+                COALESCE(i.finish_time, (i.day + TIME '12:00') AT TIME ZONE u.time_zone)
+                    AS finish_time,
+                u.time_zone
+            FROM UNNEST($2::TIMESTAMPTZ[], $3::DATE[]) WITH ORDINALITY AS i(finish_time, day, ord)
+            JOIN users u ON u.id = $1
+        )
+        SELECT finish_time, (finish_time AT TIME ZONE time_zone)::date
+        FROM rows
+        ORDER BY ord
+        "#,
+    )
+    .bind(user_id)
+    .bind(times)
+    .bind(days)
+    .fetch_all(conn)
+    .await?;
+
+    Ok(resolved)
+}
+
+/// The user's records of the given actions as `(action_id, finish_time,
+/// local_day, day_slot)`, to check imported rows against.
+pub async fn get_existing_import_records(
+    conn: &mut PgConnection,
+    user_id: i64,
+    action_ids: &[i64],
+) -> Result<Vec<(i64, OffsetDateTime, Date, i32)>, sqlx::Error> {
+    let records = sqlx::query_as::<_, (i64, OffsetDateTime, Date, i32)>(
+        r#"
+        SELECT action_id, finish_time, local_day, day_slot
+        FROM practice_record
+        WHERE user_id = $1 AND action_id = ANY($2)
+        "#,
+    )
+    .bind(user_id)
+    .bind(action_ids)
+    .fetch_all(conn)
+    .await?;
+
+    Ok(records)
+}
+
+/// Inserts imported records in bulk and moves each action's
+/// `last_finish_time` up to its latest record.
+pub async fn insert_imported_records(
+    conn: &mut PgConnection,
+    user_id: i64,
+    records: &[ImportedRecord],
+) -> Result<(), sqlx::Error> {
+    let action_ids: Vec<i64> = records.iter().map(|r| r.action_id).collect();
+    let finish_times: Vec<OffsetDateTime> = records.iter().map(|r| r.finish_time).collect();
+    let notes: Vec<Option<String>> = records.iter().map(|r| r.note.clone()).collect();
+    let quantities: Vec<Option<i32>> = records.iter().map(|r| r.quantity).collect();
+    let durations: Vec<Option<i32>> = records.iter().map(|r| r.duration_seconds).collect();
+    let local_days: Vec<Date> = records.iter().map(|r| r.local_day).collect();
+    let day_slots: Vec<i32> = records.iter().map(|r| r.day_slot).collect();
+
+    sqlx::query(
+        r#"
+        INSERT INTO practice_record (
+            action_id, user_id, finish_time, note, quantity, duration_seconds, local_day, day_slot
+        )
+        SELECT
+            i.action_id, $1, i.finish_time, i.note, i.quantity, i.duration_seconds,
+            i.local_day, i.day_slot
+        FROM UNNEST(
+            $2::BIGINT[], $3::TIMESTAMPTZ[], $4::TEXT[], $5::INT[], $6::INT[], $7::DATE[],
+            $8::INT[]
+        ) AS i(action_id, finish_time, note, quantity, duration_seconds, local_day, day_slot)
+        "#,
+    )
+    .bind(user_id)
+    .bind(&action_ids)
+    .bind(&finish_times)
+    .bind(&notes)
+    .bind(&quantities)
+    .bind(&durations)
+    .bind(&local_days)
+    .bind(&day_slots)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        r#"
+        UPDATE practice_action a
+        SET last_finish_time = latest.finish_time
+        FROM (
+            SELECT action_id, MAX(finish_time) AS finish_time
+            FROM practice_record
+            WHERE action_id = ANY($1)
+            GROUP BY action_id
+        ) latest
+        WHERE a.id = latest.action_id
+        "#,
+    )
+    .bind(&action_ids)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Streams every action the user can see, archived ones included, in manual
+/// order followed by group actions.
+pub fn stream_export_actions(
+    pool: &PgPool,
+    user_id: i64,
+) -> impl Stream<Item = Result<ExportAction, sqlx::Error>> + '_ {
+    sqlx::query_as::<_, ExportAction>(
+        r#"
+        SELECT
+            a.id, a.user_id, a.name, a.create_time, a.last_finish_time, a.archived,
+            a.paused_until, a.schedule, a.daily_target, a.unit, a.position, a.group_id,
+            ARRAY(
+                SELECT t.name
+                FROM action_tag atag
+                JOIN tag t ON atag.tag_id = t.id
+                WHERE atag.action_id = a.id AND t.user_id = $1
+                ORDER BY t.name
+            ) AS tags,
+            (a.create_time AT TIME ZONE u.time_zone)::date AS start_date
+        FROM practice_action a
+        JOIN users u ON u.id = $1
+        WHERE (
+            (a.group_id IS NULL AND a.user_id = $1)
+            OR EXISTS (
+                SELECT 1 FROM group_member m
+                WHERE m.group_id = a.group_id AND m.user_id = $1
+            )
+        )
+        ORDER BY a.group_id IS NOT NULL, CASE WHEN a.group_id IS NULL THEN a.position END, a.id
+        "#,
+    )
+    .bind(user_id)
+    .fetch(pool)
+}
+
+/// Streams the user's own records of the actions the user can see, oldest
+/// first.
+pub fn stream_export_records(
+    pool: &PgPool,
+    user_id: i64,
+) -> impl Stream<Item = Result<ExportRecord, sqlx::Error>> + '_ {
+    sqlx::query_as::<_, ExportRecord>(
+        r#"
+        SELECT
+            r.id, r.action_id, r.user_id, r.finish_time, r.note, r.quantity, r.duration_seconds,
+            a.name AS action_name,
+            a.unit,
+            r.local_day AS local_date
+        FROM practice_record r
+        JOIN practice_action a ON r.action_id = a.id
+        WHERE r.user_id = $1
+        AND (
+            (a.group_id IS NULL AND a.user_id = $1)
+            OR EXISTS (
+                SELECT 1 FROM group_member m
+                WHERE m.group_id = a.group_id AND m.user_id = $1
+            )
+        )
+        ORDER BY r.finish_time, r.id
+        "#,
+    )
+    .bind(user_id)
+    .fetch(pool)
+}
+
+/// Every transaction with a lower id than this has finished, so that all
+/// changes the next queries miss come from transactions with at least this
+/// id.
+pub async fn get_sync_horizon(pool: &PgPool) -> Result<i64, sqlx::Error> {
+    let xid = sqlx::query_scalar("SELECT pg_snapshot_xmin(pg_current_snapshot())::TEXT::BIGINT")
+        .fetch_one(pool)
+        .await?;
+
+    Ok(xid)
+}
+
+/// The change following the first `limit` changes after `since_seq` the user
+/// can see, if there are more.
+pub async fn get_sync_cutoff(
+    pool: &PgPool,
+    user_id: i64,
+    since_seq: i64,
+    include_tombstones: bool,
+    limit: i64,
+) -> Result<Option<i64>, sqlx::Error> {
+    let cutoff = sqlx::query_scalar(
+        r#"
+        SELECT change_seq FROM (
+            SELECT a.change_seq
+            FROM practice_action a
+            WHERE a.change_seq > $2
+            AND (
+                (a.group_id IS NULL AND a.user_id = $1)
+                OR EXISTS (
+                    SELECT 1 FROM group_member m
+                    WHERE m.group_id = a.group_id AND m.user_id = $1
+                )
+            )
+            UNION ALL
+            SELECT r.change_seq
+            FROM practice_record r
+            WHERE r.user_id = $1 AND r.change_seq > $2
+            UNION ALL
+            SELECT t.change_seq
+            FROM sync_tombstone t
+            WHERE $3 AND t.change_seq > $2
+            AND (
+                (t.group_id IS NULL AND t.user_id = $1)
+                OR EXISTS (
+                    SELECT 1 FROM group_member m
+                    WHERE m.group_id = t.group_id AND m.user_id = $1
+                )
+            )
+        ) changes
+        ORDER BY change_seq
+        OFFSET $4
+        LIMIT 1
+        "#,
+    )
+    .bind(user_id)
+    .bind(since_seq)
+    .bind(include_tombstones)
+    .bind(limit)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(cutoff)
+}
+
+/// Actions the user can see that changed after `since_seq` up to `until_seq`,
+/// or in a transaction with an id of at least `since_xid`.
+pub async fn get_synced_actions(
+    pool: &PgPool,
+    user_id: i64,
+    since_seq: i64,
+    until_seq: Option<i64>,
+    since_xid: Option<i64>,
+) -> Result<Vec<SyncAction>, sqlx::Error> {
+    let actions = sqlx::query_as::<_, SyncAction>(
+        r#"
+        SELECT
+            a.client_id, a.id, a.user_id, a.group_id, a.name, a.create_time, a.update_time,
+            a.last_finish_time, a.archived, a.paused_until, a.schedule, a.daily_target, a.unit,
+            a.position, a.change_seq
+        FROM practice_action a
+        WHERE (
+            (a.change_seq > $2 AND ($3::BIGINT IS NULL OR a.change_seq <= $3))
+            OR a.change_xid >= $4
+        )
+        AND (
+            (a.group_id IS NULL AND a.user_id = $1)
+            OR EXISTS (
+                SELECT 1 FROM group_member m
+                WHERE m.group_id = a.group_id AND m.user_id = $1
+            )
+        )
+        ORDER BY a.change_seq
+        "#,
+    )
+    .bind(user_id)
+    .bind(since_seq)
+    .bind(until_seq)
+    .bind(since_xid)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(actions)
+}
+
+/// The user's own records changed like in [`get_synced_actions`].
+pub async fn get_synced_records(
+    pool: &PgPool,
+    user_id: i64,
+    since_seq: i64,
+    until_seq: Option<i64>,
+    since_xid: Option<i64>,
+) -> Result<Vec<SyncRecord>, sqlx::Error> {
+    let records = sqlx::query_as::<_, SyncRecord>(
+        r#"
+        SELECT
+            r.client_id, a.client_id AS action_client_id, r.id, r.action_id, r.user_id,
+            r.finish_time, r.note, r.quantity, r.duration_seconds, r.update_time, r.change_seq
+        FROM practice_record r
+        JOIN practice_action a ON r.action_id = a.id
+        WHERE r.user_id = $1
+        AND (
+            (r.change_seq > $2 AND ($3::BIGINT IS NULL OR r.change_seq <= $3))
+            OR r.change_xid >= $4
+        )
+        ORDER BY r.change_seq
+        "#,
+    )
+    .bind(user_id)
+    .bind(since_seq)
+    .bind(until_seq)
+    .bind(since_xid)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records)
+}
+
+/// Deletions the user sees, selected like in [`get_synced_actions`].
+pub async fn get_sync_tombstones(
+    pool: &PgPool,
+    user_id: i64,
+    since_seq: i64,
+    until_seq: Option<i64>,
+    since_xid: Option<i64>,
+) -> Result<Vec<SyncTombstone>, sqlx::Error> {
+    let tombstones = sqlx::query_as::<_, SyncTombstone>(
+        r#"
+        SELECT t.kind, t.client_id, t.delete_time, t.change_seq
+        FROM sync_tombstone t
+        WHERE (
+            (t.change_seq > $2 AND ($3::BIGINT IS NULL OR t.change_seq <= $3))
+            OR t.change_xid >= $4
+        )
+        AND (
+            (t.group_id IS NULL AND t.user_id = $1)
+            OR EXISTS (
+                SELECT 1 FROM group_member m
+                WHERE m.group_id = t.group_id AND m.user_id = $1
+            )
+        )
+        ORDER BY t.change_seq
+        "#,
+    )
+    .bind(user_id)
+    .bind(since_seq)
+    .bind(until_seq)
+    .bind(since_xid)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(tombstones)
+}
+
+/// Whether an action the user could see, or a record of the user's own, with
+/// the given client id was deleted.
+pub async fn is_sync_tombstone(
+    pool: &PgPool,
+    user_id: i64,
+    kind: SyncKind,
+    client_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let deleted = sqlx::query_scalar(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM sync_tombstone t
+            WHERE t.client_id = $1 AND t.kind = $2
+            AND (
+                (t.group_id IS NULL AND t.user_id = $3)
+                OR EXISTS (
+                    SELECT 1 FROM group_member m
+                    WHERE m.group_id = t.group_id AND m.user_id = $3
+                )
+            )
+        )
+        "#,
+    )
+    .bind(client_id)
+    .bind(kind.as_sql())
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(deleted)
+}
+
+/// The id of the action the user can see, or of the user's own record, with
+/// the given client id.
+pub async fn get_sync_target(
+    pool: &PgPool,
+    user_id: i64,
+    kind: SyncKind,
+    client_id: Uuid,
+) -> Result<Option<i64>, sqlx::Error> {
+    let query = match kind {
+        SyncKind::Action => {
+            r#"
+            SELECT id FROM practice_action a
+            WHERE client_id = $1
+            AND (
+                (a.group_id IS NULL AND a.user_id = $2)
+                OR EXISTS (
+                    SELECT 1 FROM group_member m
+                    WHERE m.group_id = a.group_id AND m.user_id = $2
+                )
+            )
+            "#
+        }
+        SyncKind::Record => {
+            r#"
+            SELECT id FROM practice_record
+            WHERE client_id = $1 AND user_id = $2
+            "#
+        }
+    };
+    let id = sqlx::query_scalar(query)
+        .bind(client_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(id)
+}
+
+/// Creates the action, or overwrites it unless it changed on the server after
+/// the client's change. Group actions can be changed by their creator and
+/// the group's admins, and created by the group's members.
+pub async fn upsert_synced_action(
+    pool: &PgPool,
+    user_id: i64,
+    change: &SyncActionChange,
+) -> Result<SyncOutcome, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let existing: Option<(OffsetDateTime, bool, bool)> = sqlx::query_as(
+        r#"
+        SELECT
+            a.update_time,
+            (a.group_id IS NULL AND a.user_id = $2) OR EXISTS (
+                SELECT 1 FROM group_member m
+                WHERE m.group_id = a.group_id AND m.user_id = $2
+            ) AS visible,
+            (a.group_id IS NULL AND a.user_id = $2) OR EXISTS (
+                SELECT 1 FROM group_member m
+                WHERE m.group_id = a.group_id AND m.user_id = $2
+                AND (m.role IN ('owner', 'admin') OR a.user_id = $2)
+            ) AS manageable
+        FROM practice_action a
+        WHERE a.client_id = $1
+        FOR UPDATE
+        "#,
+    )
+    .bind(change.client_id)
+    .bind(user_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let schedule = Json(change.schedule.clone().unwrap_or_default());
+    let daily_target = change.daily_target.unwrap_or(1);
+    match existing {
+        None => {
+            let action = sqlx::query_as::<_, PracticeAction>(
+                r#"
+                INSERT INTO practice_action (
+                    client_id, user_id, name, create_time, update_time, archived, paused_until,
+                    schedule, daily_target, unit, position, group_id
+                )
+                SELECT
+                    $1, $2, $3, $4, $4, $5, $6, $7, $8, $9,
+                    (
+                        SELECT COALESCE(MAX(position) + 1, 0) FROM practice_action
+                        WHERE user_id = $2 AND group_id IS NULL
+                    ),
+                    $10
+                WHERE $10::BIGINT IS NULL OR EXISTS (
+                    SELECT 1 FROM group_member m
+                    WHERE m.group_id = $10 AND m.user_id = $2
+                )
+                ON CONFLICT (client_id) DO NOTHING
+                RETURNING id, user_id, name, create_time, last_finish_time, archived, paused_until,
+                    schedule, daily_target, unit, position, group_id
+                "#,
+            )
+            .bind(change.client_id)
+            .bind(user_id)
+            .bind(&change.name)
+            .bind(change.modified_time)
+            .bind(change.archived)
+            .bind(change.paused_until)
+            .bind(schedule)
+            .bind(daily_target)
+            .bind(&change.unit)
+            .bind(change.group_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+            let Some(action) = action else {
+                return Ok(match change.group_id {
+                    Some(_) => SyncOutcome::NotFound,
+                    None => SyncOutcome::Stale, // created meanwhile by another sync
+                });
+            };
+
+            enqueue_webhook_event(
+                &mut tx,
+                user_id,
+                WebhookEvent::ActionCreated,
+                &json!({ "action": &action }),
+            )
+            .await?;
+        }
+        Some((_, false, _)) => return Ok(SyncOutcome::NotFound),
+        Some((_, true, false)) => return Ok(SyncOutcome::Forbidden),
+        Some((update_time, true, true)) => {
+            if update_time > change.modified_time {
+                return Ok(SyncOutcome::Stale);
+            }
+            sqlx::query(
+                r#"
+                UPDATE practice_action
+                SET name = $2,
+                    update_time = $3,
+                    archived = $4,
+                    paused_until = $5,
+                    schedule = $6,
+                    daily_target = $7,
+                    unit = $8
+                WHERE client_id = $1
+                "#,
+            )
+            .bind(change.client_id)
+            .bind(&change.name)
+            .bind(change.modified_time)
+            .bind(change.archived)
+            .bind(change.paused_until)
+            .bind(schedule)
+            .bind(daily_target)
+            .bind(&change.unit)
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(SyncOutcome::Applied)
+}
+
+/// Changes the details of an existing record unless they changed on the
+/// server after the client's change. Returns `None` if there is no record
+/// with the client id.
+pub async fn update_synced_record(
+    pool: &PgPool,
+    user_id: i64,
+    change: &SyncRecordChange,
+) -> Result<Option<SyncOutcome>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let existing: Option<(i64, OffsetDateTime)> = sqlx::query_as(
+        r#"
+        SELECT user_id, update_time
+        FROM practice_record
+        WHERE client_id = $1
+        FOR UPDATE
+        "#,
+    )
+    .bind(change.client_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let outcome = match existing {
+        None => return Ok(None),
+        Some((owner, _)) if owner != user_id => SyncOutcome::NotFound,
+        Some((_, update_time)) if update_time > change.modified_time => SyncOutcome::Stale,
+        Some(_) => {
+            sqlx::query(
+                r#"
+                UPDATE practice_record
+                SET note = $2, quantity = $3, duration_seconds = $4, update_time = $5
+                WHERE client_id = $1
+                "#,
+            )
+            .bind(change.client_id)
+            .bind(&change.note)
+            .bind(change.quantity)
+            .bind(change.duration_seconds)
+            .bind(change.modified_time)
+            .execute(&mut *tx)
+            .await?;
+            SyncOutcome::Applied
+        }
+    };
+
+    tx.commit().await?;
+
+    Ok(Some(outcome))
+}
+
+pub async fn create_user_webhook(
+    pool: &PgPool,
+    user_id: i64,
+    url: &str,
+    secret: &str,
+    events: &[&str],
+) -> Result<Webhook, sqlx::Error> {
+    let webhook = sqlx::query_as::<_, Webhook>(
+        r#"
+        INSERT INTO webhook (user_id, url, secret, events, create_time)
+        VALUES ($1, $2, $3, $4, NOW())
+        RETURNING id, url, events, active, create_time
+        "#,
+    )
+    .bind(user_id)
+    .bind(url)
+    .bind(secret)
+    .bind(events)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(webhook)
+}
+
+pub async fn list_user_webhooks(pool: &PgPool, user_id: i64) -> Result<Vec<Webhook>, sqlx::Error> {
+    let webhooks = sqlx::query_as::<_, Webhook>(
+        r#"
+        SELECT id, url, events, active, create_time
+        FROM webhook
+        WHERE user_id = $1
+        ORDER BY id
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(webhooks)
+}
+
+/// Applies the given changes; fields left as `None` keep their current value.
+pub async fn update_user_webhook(
+    pool: &PgPool,
+    user_id: i64,
+    id: i64,
+    url: Option<&str>,
+    events: Option<&[&str]>,
+    active: Option<bool>,
+) -> Result<Option<Webhook>, sqlx::Error> {
+    let webhook = sqlx::query_as::<_, Webhook>(
+        r#"
+        UPDATE webhook
+        SET url = COALESCE($1, url),
+            events = COALESCE($2, events),
+            active = COALESCE($3, active)
+        WHERE id = $4 AND user_id = $5
+        RETURNING id, url, events, active, create_time
+        "#,
+    )
+    .bind(url)
+    .bind(events)
+    .bind(active)
+    .bind(id)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(webhook)
+}
+
+/// Deletes the webhook together with its delivery log. Returns false if the
+/// webhook does not exist or belongs to another user.
+pub async fn delete_user_webhook(
+    pool: &PgPool,
+    user_id: i64,
+    id: i64,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM webhook
+        WHERE id = $1 AND user_id = $2
+        "#,
+    )
+    .bind(id)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Deliveries of the webhook, newest first.
+pub async fn get_webhook_deliveries(
+    pool: &PgPool,
+    user_id: i64,
+    webhook_id: i64,
+    status: Option<DeliveryStatus>,
+    before: Option<i64>,
+    limit: i64,
+) -> Result<Vec<WebhookDelivery>, sqlx::Error> {
+    let deliveries = sqlx::query_as::<_, WebhookDelivery>(
+        r#"
+        SELECT
+            d.id, d.webhook_id, d.event, d.payload, d.status, d.attempts, d.next_attempt_time,
+            d.last_attempt_time, d.response_status, d.last_error, d.create_time
+        FROM webhook_delivery d
+        JOIN webhook w ON d.webhook_id = w.id
+        WHERE d.webhook_id = $1 AND w.user_id = $2
+        AND ($3::TEXT IS NULL OR d.status = $3)
+        AND ($4::BIGINT IS NULL OR d.id < $4)
+        ORDER BY d.id DESC
+        LIMIT $5
+        "#,
+    )
+    .bind(webhook_id)
+    .bind(user_id)
+    .bind(status.map(|status| status.as_sql()))
+    .bind(before)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(deliveries)
+}
+
+/// Queues the event for every active webhook of the user subscribed to it.
+/// Runs on the caller's transaction, so events are only sent for changes
+/// that were committed.
+pub async fn enqueue_webhook_event(
+    conn: &mut PgConnection,
+    user_id: i64,
+    event: WebhookEvent,
+    payload: &serde_json::Value,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO webhook_delivery (webhook_id, event, payload, next_attempt_time, create_time)
+        SELECT id, $2, $3, NOW(), NOW()
+        FROM webhook
+        WHERE user_id = $1 AND active AND $2 = ANY(events)
+        "#,
+    )
+    .bind(user_id)
+    .bind(event.as_str())
+    .bind(payload)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Claims up to `limit` pending deliveries of active webhooks that are due,
+/// marking those of disabled webhooks as failed. Claimed deliveries are not
+/// handed out again for `lease_seconds`, in case the sender dies before
+/// recording the outcome.
+pub async fn claim_webhook_deliveries(
+    pool: &PgPool,
+    limit: i64,
+    lease_seconds: i64,
+) -> Result<Vec<PendingDelivery>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE webhook_delivery d
+        SET status = 'failed', last_error = 'Webhook was disabled'
+        FROM webhook w
+        WHERE d.webhook_id = w.id AND d.status = 'pending' AND NOT w.active
+        AND d.next_attempt_time <= NOW()
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    let deliveries = sqlx::query_as::<_, PendingDelivery>(
+        r#"
+        WITH due AS (
+            SELECT d.id
+            FROM webhook_delivery d
+            JOIN webhook w ON w.id = d.webhook_id
+            WHERE d.status = 'pending' AND d.next_attempt_time <= NOW() AND w.active
+            ORDER BY d.next_attempt_time
+            LIMIT $1
+            FOR UPDATE OF d SKIP LOCKED
+        )
+        UPDATE webhook_delivery d
+        SET next_attempt_time = NOW() + make_interval(secs => $2)
+        FROM due, webhook w
+        WHERE d.id = due.id AND d.webhook_id = w.id
+        RETURNING d.id, d.event, d.payload, d.attempts, d.create_time, w.url, w.secret
+        "#,
+    )
+    .bind(limit)
+    .bind(lease_seconds as f64)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(deliveries)
+}
+
+/// Records one delivery attempt. A failed attempt is retried at `retry_time`,
+/// or marks the delivery as failed when there is none.
+pub async fn record_delivery_attempt(
+    pool: &PgPool,
+    id: i64,
+    delivered: bool,
+    response_status: Option<i32>,
+    error: Option<&str>,
+    retry_time: Option<OffsetDateTime>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE webhook_delivery
+        SET attempts = attempts + 1,
+            last_attempt_time = NOW(),
+            response_status = $2,
+            last_error = $3,
+            status = CASE
+                WHEN $4 THEN 'delivered'
+                WHEN $5::TIMESTAMPTZ IS NULL THEN 'failed'
+                ELSE 'pending'
+            END,
+            next_attempt_time = COALESCE($5, next_attempt_time)
+        WHERE id = $1
+        "#,
+    )
+    .bind(id)
+    .bind(response_status)
+    .bind(error)
+    .bind(delivered)
+    .bind(retry_time)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Actions that users subscribed to `streak.broken` can see, their own and
+/// their groups', that have not been checked for them yet on their local
+/// today. Archived and paused actions are left out.
+pub async fn get_streak_checks(pool: &PgPool, limit: i64) -> Result<Vec<StreakCheck>, sqlx::Error> {
+    let checks = sqlx::query_as::<_, StreakCheck>(
+        r#"
+        WITH subscribers AS (
+            SELECT u.id AS user_id, (now() AT TIME ZONE u.time_zone)::date AS today
+            FROM users u
+            WHERE EXISTS (
+                SELECT 1 FROM webhook w
+                WHERE w.user_id = u.id AND w.active AND 'streak.broken' = ANY(w.events)
+            )
+        )
+        SELECT a.id, s.user_id, a.name, a.schedule, s.today
+        FROM subscribers s
+        JOIN practice_action a ON (
+            (a.group_id IS NULL AND a.user_id = s.user_id)
+            OR EXISTS (
+                SELECT 1 FROM group_member m
+                WHERE m.group_id = a.group_id AND m.user_id = s.user_id
+            )
+        )
+        LEFT JOIN streak_check c ON c.action_id = a.id AND c.user_id = s.user_id
+        WHERE NOT a.archived
+        AND (a.paused_until IS NULL OR a.paused_until < s.today)
+        AND (c.checked_date IS NULL OR c.checked_date < s.today)
+        ORDER BY a.id, s.user_id
+        LIMIT $1
+        "#,
+    )
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(checks)
+}
+
+/// Marks the action as checked for the user for `today`, queueing a
+/// `streak.broken` event with `payload` when given. Returns false if another
+/// server got there first.
+pub async fn finish_streak_check(
+    pool: &PgPool,
+    check: &StreakCheck,
+    payload: Option<&serde_json::Value>,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query(
+        r#"
+        INSERT INTO streak_check (action_id, user_id, checked_date)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (action_id, user_id) DO UPDATE
+        SET checked_date = EXCLUDED.checked_date
+        WHERE streak_check.checked_date < EXCLUDED.checked_date
+        "#,
+    )
+    .bind(check.id)
+    .bind(check.user_id)
+    .bind(check.today)
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    if let Some(payload) = payload {
+        enqueue_webhook_event(&mut tx, check.user_id, WebhookEvent::StreakBroken, payload).await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+/// Claims the key for a request with the given fingerprint. Returns false if
+/// the key is taken, unless it expired or its request was abandoned for
+/// `abandon_seconds` without a response.
+pub async fn claim_idempotency_key(
+    pool: &PgPool,
+    user_id: i64,
+    key: &str,
+    fingerprint: &str,
+    ttl_seconds: i64,
+    abandon_seconds: i64,
+) -> Result<bool, sqlx::Error> {
+    let claimed = sqlx::query(
+        r#"
+        INSERT INTO idempotency_key (user_id, key, fingerprint, create_time, expire_time)
+        VALUES ($1, $2, $3, NOW(), NOW() + make_interval(secs => $4))
+        ON CONFLICT (user_id, key) DO UPDATE
+        SET fingerprint = EXCLUDED.fingerprint,
+            status = NULL,
+            content_type = NULL,
+            body = NULL,
+            create_time = EXCLUDED.create_time,
+            expire_time = EXCLUDED.expire_time
+        WHERE idempotency_key.expire_time <= NOW()
+        OR (
+            idempotency_key.status IS NULL
+            AND idempotency_key.create_time <= NOW() - make_interval(secs => $5)
+        )
+        "#,
+    )
+    .bind(user_id)
+    .bind(key)
+    .bind(fingerprint)
+    .bind(ttl_seconds as f64)
+    .bind(abandon_seconds as f64)
+    .execute(pool)
+    .await?;
+
+    Ok(claimed.rows_affected() > 0)
+}
+
+pub async fn get_idempotency_key(
+    pool: &PgPool,
+    user_id: i64,
+    key: &str,
+) -> Result<Option<IdempotencyRecord>, sqlx::Error> {
+    let record = sqlx::query_as::<_, IdempotencyRecord>(
+        r#"
+        SELECT fingerprint, status, content_type, body
+        FROM idempotency_key
+        WHERE user_id = $1 AND key = $2 AND expire_time > NOW()
+        "#,
+    )
+    .bind(user_id)
+    .bind(key)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record)
+}
+
+pub async fn save_idempotent_response(
+    pool: &PgPool,
+    user_id: i64,
+    key: &str,
+    status: i32,
+    content_type: Option<&str>,
+    body: &[u8],
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE idempotency_key
+        SET status = $3, content_type = $4, body = $5
+        WHERE user_id = $1 AND key = $2
+        "#,
+    )
+    .bind(user_id)
+    .bind(key)
+    .bind(status)
+    .bind(content_type)
+    .bind(body)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Frees a claimed key, so that a retry runs the request again.
+pub async fn release_idempotency_key(
+    pool: &PgPool,
+    user_id: i64,
+    key: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        DELETE FROM idempotency_key
+        WHERE user_id = $1 AND key = $2
+        "#,
+    )
+    .bind(user_id)
+    .bind(key)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete_expired_idempotency_keys(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM idempotency_key
+        WHERE expire_time <= NOW()
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+pub async fn create_refresh_token(
+    pool: &PgPool,
+    user_id: i64,
+    family_id: Uuid,
+    token_hash: &str,
+    expire_time: OffsetDateTime,
+) -> Result<RefreshToken, sqlx::Error> {
+    let now = OffsetDateTime::now_utc();
+
+    let token = sqlx::query_as::<_, RefreshToken>(
+        r#"
+        INSERT INTO refresh_token (user_id, family_id, token_hash, create_time, expire_time)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, user_id, family_id, expire_time, used_time, revoked_time
+        "#,
+    )
+    .bind(user_id)
+    .bind(family_id)
+    .bind(token_hash)
+    .bind(now)
+    .bind(expire_time)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(token)
+}
+
+/// Exchanges the refresh token identified by `token_hash` for a new one in the
+/// same family. Presenting a token that was already used or revoked is treated
+/// as a replay and revokes every token in its family.
+pub async fn rotate_refresh_token(
+    pool: &PgPool,
+    token_hash: &str,
+    new_token_hash: &str,
+    new_expire_time: OffsetDateTime,
+) -> Result<RefreshOutcome, sqlx::Error> {
+    let now = OffsetDateTime::now_utc();
+    let mut tx = pool.begin().await?;
+
+    let current = sqlx::query_as::<_, RefreshToken>(
+        r#"
+        SELECT id, user_id, family_id, expire_time, used_time, revoked_time
+        FROM refresh_token
+        WHERE token_hash = $1
+        FOR UPDATE
+        "#,
+    )
+    .bind(token_hash)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let current = match current {
+        Some(current) => current,
+        None => return Ok(RefreshOutcome::Invalid),
+    };
+
+    if current.used_time.is_some() {
+        sqlx::query(
+            r#"
+            UPDATE refresh_token
+            SET revoked_time = $1
+            WHERE family_id = $2 AND revoked_time IS NULL
+            "#,
+        )
+        .bind(now)
+        .bind(current.family_id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        return Ok(RefreshOutcome::Reused);
+    }
+
+    if current.revoked_time.is_some() || current.expire_time <= now {
+        return Ok(RefreshOutcome::Invalid);
+    }
+
+    sqlx::query(
+        r#"
+        UPDATE refresh_token
+        SET used_time = $1
+        WHERE id = $2
+        "#,
+    )
+    .bind(now)
+    .bind(current.id)
+    .execute(&mut *tx)
+    .await?;
+
+    let next = sqlx::query_as::<_, RefreshToken>(
+        r#"
+        INSERT INTO refresh_token (user_id, family_id, token_hash, create_time, expire_time)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, user_id, family_id, expire_time, used_time, revoked_time
+        "#,
+    )
+    .bind(current.user_id)
+    .bind(current.family_id)
+    .bind(new_token_hash)
+    .bind(now)
+    .bind(new_expire_time)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(RefreshOutcome::Rotated(next))
+}
+
+/// Revokes every refresh token of one session (a rotation family).
+pub async fn revoke_refresh_token_family(
+    pool: &PgPool,
+    user_id: i64,
+    family_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE refresh_token
+        SET revoked_time = $1
+        WHERE family_id = $2 AND user_id = $3 AND revoked_time IS NULL
+        "#,
+    )
+    .bind(OffsetDateTime::now_utc())
+    .bind(family_id)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Puts a single access token on the revocation list until it would have
+/// expired anyway, and drops entries that are past that point.
+pub async fn revoke_access_token(
+    pool: &PgPool,
+    user_id: i64,
+    jti: Uuid,
+    expire_time: OffsetDateTime,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO revoked_token (jti, user_id, expire_time)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (jti) DO NOTHING
+        "#,
+    )
+    .bind(jti)
+    .bind(user_id)
+    .bind(expire_time)
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        DELETE FROM revoked_token
+        WHERE expire_time < $1
+        "#,
+    )
+    .bind(OffsetDateTime::now_utc())
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Invalidates every access and refresh token the user holds by bumping
+/// `users.token_version`.
+pub async fn revoke_all_user_tokens(pool: &PgPool, user_id: i64) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        r#"
+        UPDATE users
+        SET token_version = token_version + 1
+        WHERE id = $1
+        "#,
+    )
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        UPDATE refresh_token
+        SET revoked_time = $1
+        WHERE user_id = $2 AND revoked_time IS NULL
+        "#,
+    )
+    .bind(OffsetDateTime::now_utc())
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+pub async fn is_token_revoked(
+    pool: &PgPool,
+    user_id: i64,
+    jti: Uuid,
+    token_version: i32,
+) -> Result<bool, sqlx::Error> {
+    let revoked: Option<bool> = sqlx::query_scalar(
+        r#"
+        SELECT NOT EXISTS(
+            SELECT 1 FROM users
+            WHERE id = $1 AND token_version = $3
+        ) OR EXISTS(
+            SELECT 1 FROM revoked_token
+            WHERE jti = $2
+        )
+        "#,
+    )
+    .bind(user_id)
+    .bind(jti)
+    .bind(token_version)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(revoked.unwrap_or(true))
 }
@@ -0,0 +1,372 @@
+//! Streams a user's actions and records as a JSON archive, CSV or iCalendar.
+//!
+//! Rows are read from the database one by one and written to a bounded
+//! channel in chunks, so an export never holds more than a few chunks in
+//! memory and stops reading once the client goes away.
+
+use serde_json::json;
+use sqlx::PgPool;
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use time::{Date, OffsetDateTime};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tracing::error;
+
+use crate::db::{stream_export_actions, stream_export_records};
+use crate::models::{ExportAction, ExportFormat, ExportRecord, User};
+use crate::schedule::Schedule;
+
+/// Bytes collected before a chunk is handed to the response body.
+const CHUNK_SIZE: usize = 16 * 1024;
+/// Chunks buffered ahead of a slow client.
+const CHANNEL_CAPACITY: usize = 4;
+/// Version of the JSON archive, checked when importing it.
+pub const ARCHIVE_VERSION: i32 = 1;
+const CSV_HEADER: &str =
+    "record_id,action_id,action,date,finish_time,quantity,unit,duration_seconds,note\r\n";
+
+pub type Chunk = Result<String, sqlx::Error>;
+
+enum ExportError {
+    Database(sqlx::Error),
+    Closed, // the client stopped reading
+}
+
+impl From<sqlx::Error> for ExportError {
+    fn from(err: sqlx::Error) -> Self {
+        ExportError::Database(err)
+    }
+}
+
+struct ChunkWriter {
+    tx: mpsc::Sender<Chunk>,
+    buf: String,
+}
+
+impl ChunkWriter {
+    async fn write(&mut self, s: &str) -> Result<(), ExportError> {
+        self.buf.push_str(s);
+        if self.buf.len() >= CHUNK_SIZE {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), ExportError> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.buf, String::with_capacity(CHUNK_SIZE));
+        self.tx
+            .send(Ok(chunk))
+            .await
+            .map_err(|_| ExportError::Closed)
+    }
+}
+
+/// Starts exporting the user's data in the background and returns the
+/// stream of chunks to send. A database error mid-way ends the stream with
+/// that error, which aborts the response.
+pub fn stream(pool: PgPool, user: User, format: ExportFormat) -> ReceiverStream<Chunk> {
+    let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+
+    tokio::spawn(async move {
+        let mut out = ChunkWriter {
+            tx: tx.clone(),
+            buf: String::with_capacity(CHUNK_SIZE),
+        };
+        let result = match format {
+            ExportFormat::Json => write_json(&pool, &user, &mut out).await,
+            ExportFormat::Csv => write_csv(&pool, &user, &mut out).await,
+            ExportFormat::Ics => write_ics(&pool, &user, &mut out).await,
+        };
+        let result = match result {
+            Ok(()) => out.flush().await,
+            Err(e) => Err(e),
+        };
+        if let Err(ExportError::Database(e)) = result {
+            error!("Export for user {} failed: {}", user.id, e);
+            let _ = tx.send(Err(e)).await;
+        }
+    });
+
+    ReceiverStream::new(rx)
+}
+
+/// `{"version", "exported_at", "user", "actions": [...], "records": [...]}`,
+/// with actions and records serialized as the API returns them.
+async fn write_json(pool: &PgPool, user: &User, out: &mut ChunkWriter) -> Result<(), ExportError> {
+    let header = json!({
+        "version": ARCHIVE_VERSION,
+        "exported_at": OffsetDateTime::now_utc().unix_timestamp(),
+        "user": user,
+    })
+    .to_string();
+    // Reopen the object to append the streamed arrays
+    out.write(header.trim_end_matches('}')).await?;
+
+    out.write(",\"actions\":[").await?;
+    let mut actions = stream_export_actions(pool, user.id);
+    let mut first = true;
+    while let Some(action) = actions.next().await {
+        let action = action?;
+        out.write(if first { "\n" } else { ",\n" }).await?;
+        out.write(&to_json(&action.action)).await?;
+        first = false;
+    }
+
+    out.write("],\"records\":[").await?;
+    let mut records = stream_export_records(pool, user.id);
+    let mut first = true;
+    while let Some(record) = records.next().await {
+        let record = record?;
+        out.write(if first { "\n" } else { ",\n" }).await?;
+        out.write(&to_json(&record.record)).await?;
+        first = false;
+    }
+
+    out.write("]}\n").await
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_string(value).expect("exported rows serialize to JSON")
+}
+
+/// One row per record; `date` is the user's local day of `finish_time`.
+async fn write_csv(pool: &PgPool, user: &User, out: &mut ChunkWriter) -> Result<(), ExportError> {
+    out.write(CSV_HEADER).await?;
+
+    let mut records = stream_export_records(pool, user.id);
+    while let Some(row) = records.next().await {
+        let row = row?;
+        let record = &row.record;
+        let fields = [
+            record.id.to_string(),
+            record.action_id.to_string(),
+            csv_field(&row.action_name),
+            row.local_date.to_string(),
+            format_rfc3339(record.finish_time),
+            record.quantity.map(|q| q.to_string()).unwrap_or_default(),
+            csv_field(row.unit.as_deref().unwrap_or_default()),
+            record
+                .duration_seconds
+                .map(|d| d.to_string())
+                .unwrap_or_default(),
+            csv_field(record.note.as_deref().unwrap_or_default()),
+        ];
+        out.write(&fields.join(",")).await?;
+        out.write("\r\n").await?;
+    }
+    Ok(())
+}
+
+/// Quotes fields containing separators, quotes or line breaks (RFC 4180).
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn format_rfc3339(time: OffsetDateTime) -> String {
+    time.format(&Rfc3339)
+        .expect("timestamps format as RFC 3339")
+}
+
+/// A calendar with one all-day recurring event per non-archived action that
+/// follows its schedule, and one event per completion.
+async fn write_ics(pool: &PgPool, user: &User, out: &mut ChunkWriter) -> Result<(), ExportError> {
+    let stamp = format_ics_time(OffsetDateTime::now_utc());
+    out.write("BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//rust-todo//export//EN\r\n")
+        .await?;
+    out.write("CALSCALE:GREGORIAN\r\n").await?;
+
+    let mut actions = stream_export_actions(pool, user.id);
+    while let Some(action) = actions.next().await {
+        let action = action?;
+        if !action.action.archived {
+            out.write(&schedule_event(&action, &stamp)).await?;
+        }
+    }
+
+    let mut records = stream_export_records(pool, user.id);
+    while let Some(record) = records.next().await {
+        out.write(&completion_event(&record?, &stamp)).await?;
+    }
+
+    out.write("END:VCALENDAR\r\n").await
+}
+
+fn schedule_event(export: &ExportAction, stamp: &str) -> String {
+    let action = &export.action;
+    let (rule, description) = match &action.schedule.0 {
+        Schedule::Daily => ("FREQ=DAILY".to_string(), None),
+        Schedule::EveryNDays { interval } => (format!("FREQ=DAILY;INTERVAL={}", interval), None),
+        Schedule::Weekdays { days } => {
+            let days: Vec<&str> = days.iter().map(|&d| ics_weekday(d)).collect();
+            (format!("FREQ=WEEKLY;BYDAY={}", days.join(",")), None)
+        }
+        // Any days of the week will do, so mark the week instead
+        Schedule::TimesPerWeek { times } => (
+            "FREQ=WEEKLY".to_string(),
+            Some(format!("{} times per week", times)),
+        ),
+    };
+
+    let mut lines = vec![
+        "BEGIN:VEVENT".to_string(),
+        format!("UID:action-{}@rust-todo", action.id),
+        format!("DTSTAMP:{}", stamp),
+        format!("DTSTART;VALUE=DATE:{}", format_ics_date(export.start_date)),
+        format!("RRULE:{}", rule),
+        format!("SUMMARY:{}", ics_text(&action.name)),
+    ];
+    if let Some(description) = description {
+        lines.push(format!("DESCRIPTION:{}", ics_text(&description)));
+    }
+    lines.push("END:VEVENT".to_string());
+    ics_lines(&lines)
+}
+
+/// A completion as an event ending at `finish_time`, lasting
+/// `duration_seconds` when recorded.
+fn completion_event(row: &ExportRecord, stamp: &str) -> String {
+    let record = &row.record;
+    let start = record.finish_time
+        - time::Duration::seconds(i64::from(record.duration_seconds.unwrap_or(0)));
+
+    let mut details = Vec::new();
+    if let Some(quantity) = record.quantity {
+        details.push(match &row.unit {
+            Some(unit) => format!("{} {}", quantity, unit),
+            None => quantity.to_string(),
+        });
+    }
+    if let Some(note) = &record.note {
+        details.push(note.clone());
+    }
+
+    let mut lines = vec![
+        "BEGIN:VEVENT".to_string(),
+        format!("UID:record-{}@rust-todo", record.id),
+        format!("DTSTAMP:{}", stamp),
+        format!("DTSTART:{}", format_ics_time(start)),
+        format!("DTEND:{}", format_ics_time(record.finish_time)),
+        format!("SUMMARY:{}", ics_text(&row.action_name)),
+    ];
+    if !details.is_empty() {
+        lines.push(format!("DESCRIPTION:{}", ics_text(&details.join("\n"))));
+    }
+    lines.push("END:VEVENT".to_string());
+    ics_lines(&lines)
+}
+
+fn ics_weekday(day: u8) -> &'static str {
+    match day {
+        1 => "MO",
+        2 => "TU",
+        3 => "WE",
+        4 => "TH",
+        5 => "FR",
+        6 => "SA",
+        _ => "SU",
+    }
+}
+
+fn format_ics_time(time: OffsetDateTime) -> String {
+    let format = format_description!("[year][month][day]T[hour][minute][second]Z");
+    time.to_offset(time::UtcOffset::UTC)
+        .format(&format)
+        .expect("timestamps format as iCalendar UTC times")
+}
+
+fn format_ics_date(date: Date) -> String {
+    let format = format_description!("[year][month][day]");
+    date.format(&format)
+        .expect("dates format as iCalendar dates")
+}
+
+/// Escapes a TEXT value (RFC 5545, section 3.3.11).
+fn ics_text(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace(['\r', '\n'], "\\n")
+}
+
+/// Joins content lines with CRLF, folding those longer than 75 octets.
+fn ics_lines(lines: &[String]) -> String {
+    let mut out = String::new();
+    for line in lines {
+        let mut width = 0;
+        for c in line.chars() {
+            if width + c.len_utf8() > 75 {
+                out.push_str("\r\n ");
+                width = 1;
+            }
+            out.push(c);
+            width += c.len_utf8();
+        }
+        out.push_str("\r\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_field_quotes_only_when_needed() {
+        assert_eq!(csv_field("plain text"), "plain text");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("two\r\nlines"), "\"two\r\nlines\"");
+        assert_eq!(csv_field(""), "");
+    }
+
+    #[test]
+    fn ics_text_escapes() {
+        assert_eq!(ics_text("a;b,c\\d"), r"a\;b\,c\\d");
+        assert_eq!(
+            ics_text("one\r\ntwo\nthree\rfour"),
+            "one\\ntwo\\nthree\\nfour"
+        );
+    }
+
+    #[test]
+    fn ics_lines_fold_at_75_octets() {
+        let short = "SUMMARY:Read".to_string();
+        assert_eq!(ics_lines(&[short]), "SUMMARY:Read\r\n");
+
+        let exact = "x".repeat(75);
+        assert_eq!(
+            ics_lines(std::slice::from_ref(&exact)),
+            format!("{}\r\n", exact)
+        );
+
+        let long = format!("DESCRIPTION:{}", "y".repeat(150));
+        let folded = ics_lines(std::slice::from_ref(&long));
+        for line in folded.trim_end_matches("\r\n").split("\r\n") {
+            assert!(line.len() <= 75, "{:?} is longer than 75 octets", line);
+        }
+        assert_eq!(folded.replace("\r\n ", ""), format!("{}\r\n", long));
+    }
+
+    #[test]
+    fn ics_lines_do_not_split_characters() {
+        // Two-octet characters, so that the 75th octet falls within one
+        let long = format!("XY{}", "é".repeat(50));
+        let folded = ics_lines(std::slice::from_ref(&long));
+        let mut lines = folded.trim_end_matches("\r\n").split("\r\n");
+        assert_eq!(lines.next().unwrap().len(), 74);
+        assert!(lines.all(|line| line.len() <= 75 && line.starts_with(' ')));
+        assert_eq!(folded.replace("\r\n ", ""), format!("{}\r\n", long));
+    }
+}
@@ -0,0 +1,329 @@
+//! Replays responses to POST, PATCH and DELETE requests sent with an
+//! `Idempotency-Key` header, so that clients can safely retry them.
+//!
+//! The first request with a key claims it for the user, together with a
+//! fingerprint of its method, path, content type and body, and stores the
+//! response once the handler finishes. A retry with the same key gets the
+//! stored response back without running the handler again, while a different
+//! request with the same key is rejected. Server errors and responses larger
+//! than `MAX_STORED_BYTES` are not stored, so a retry after one runs the
+//! request again.
+//!
+//! The user is authenticated before the key is claimed, so a request without
+//! a valid token gets 401 from here rather than from the handler, and nothing
+//! is stored for it.
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{FromRequestParts, Request, State},
+    http::{header::CONTENT_TYPE, request, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+use tokio_stream::StreamExt;
+use tracing::{error, info};
+
+use crate::auth::AuthUser;
+use crate::db::{
+    claim_idempotency_key, delete_expired_idempotency_keys, get_idempotency_key,
+    release_idempotency_key, save_idempotent_response,
+};
+use crate::models::IdempotencyRecord;
+use crate::{AppError, AppState};
+
+const HEADER: &str = "idempotency-key";
+/// Set on replayed responses.
+const REPLAYED_HEADER: &str = "idempotent-replayed";
+const MAX_KEY_LENGTH: usize = 255;
+/// A key whose request has not answered for this long, e.g. because the
+/// server restarted, may be claimed again.
+const ABANDON_SECS: i64 = 300;
+/// Larger responses are passed through without storing them.
+const MAX_STORED_BYTES: usize = 1024 * 1024;
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Deletes expired keys every hour for as long as the server runs.
+pub fn spawn(pool: PgPool) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(CLEANUP_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            match delete_expired_idempotency_keys(&pool).await {
+                Ok(0) => {}
+                Ok(count) => info!("Deleted {} expired idempotency keys", count),
+                Err(e) => error!("Deleting expired idempotency keys failed: {}", e),
+            }
+        }
+    });
+}
+
+/// Middleware for the authenticated routes, with the body limit of the routes
+/// it is applied to; the body is read here to fingerprint it.
+pub async fn replay(
+    State((state, body_limit)): State<(Arc<AppState>, usize)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match handle(&state, body_limit, request, next).await {
+        Ok(response) => response,
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Middleware for the routes that issue tokens, whose responses are never
+/// stored; a key sent there is refused so that the client doesn't take the
+/// request for a replayable one.
+pub async fn reject(request: Request, next: Next) -> Response {
+    if request.headers().contains_key(HEADER) {
+        return AppError(
+            StatusCode::BAD_REQUEST,
+            "Idempotency-Key is not supported for this request".to_string(),
+        )
+        .into_response();
+    }
+    next.run(request).await
+}
+
+async fn handle(
+    state: &Arc<AppState>,
+    body_limit: usize,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let mutating = matches!(
+        *request.method(),
+        Method::POST | Method::PATCH | Method::DELETE
+    );
+    let Some(key) = request.headers().get(HEADER).filter(|_| mutating) else {
+        return Ok(next.run(request).await);
+    };
+    let key = key
+        .to_str()
+        .ok()
+        .map(str::trim)
+        .filter(|k| !k.is_empty() && k.len() <= MAX_KEY_LENGTH)
+        .ok_or_else(|| {
+            AppError(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Idempotency-Key must be 1 to {} visible ASCII characters",
+                    MAX_KEY_LENGTH
+                ),
+            )
+        })?
+        .to_string();
+
+    let (mut parts, body) = request.into_parts();
+    let user = AuthUser::from_request_parts(&mut parts, state).await?;
+    let body = to_bytes(body, body_limit)
+        .await
+        .map_err(|_| AppError(StatusCode::PAYLOAD_TOO_LARGE, "Body too large".to_string()))?;
+    let fingerprint = fingerprint(&parts, &body);
+
+    let pool = &state.pool;
+    let ttl = *crate::IDEMPOTENCY_TTL_SECS;
+    if !claim_idempotency_key(pool, user.user_id, &key, &fingerprint, ttl, ABANDON_SECS).await? {
+        let stored = get_idempotency_key(pool, user.user_id, &key).await?;
+        return replayed(stored, &fingerprint);
+    }
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    let status = response.status();
+    if status.is_server_error() {
+        if let Err(e) = release_idempotency_key(pool, user.user_id, &key).await {
+            error!("Releasing idempotency key failed: {}", e);
+        }
+        return Ok(response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let mut chunks = body.into_data_stream();
+    let mut body = Vec::new();
+    while let Some(chunk) = chunks.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                error!("Reading response for idempotency key failed: {}", e);
+                if let Err(e) = release_idempotency_key(pool, user.user_id, &key).await {
+                    error!("Releasing idempotency key failed: {}", e);
+                }
+                return Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+            }
+        };
+        if body.len() + chunk.len() > MAX_STORED_BYTES {
+            if let Err(e) = release_idempotency_key(pool, user.user_id, &key).await {
+                error!("Releasing idempotency key failed: {}", e);
+            }
+            // Send what was read so far, followed by the rest
+            let read = tokio_stream::iter([Ok(Bytes::from(body)), Ok(chunk)]);
+            return Ok(Response::from_parts(
+                parts,
+                Body::from_stream(read.chain(chunks)),
+            ));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let content_type = parts
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok());
+    // The request already happened, so answer it even if storing fails
+    if let Err(e) = save_idempotent_response(
+        pool,
+        user.user_id,
+        &key,
+        i32::from(status.as_u16()),
+        content_type,
+        &body,
+    )
+    .await
+    {
+        error!("Saving idempotent response failed: {}", e);
+    }
+
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+/// Hex SHA-256 of the request's method, path, content type and body.
+fn fingerprint(parts: &request::Parts, body: &[u8]) -> String {
+    let content_type = parts
+        .headers
+        .get(CONTENT_TYPE)
+        .map_or(&[][..], HeaderValue::as_bytes);
+    let mut hasher = Sha256::new();
+    hasher.update(parts.method.as_str());
+    hasher.update(b" ");
+    hasher.update(parts.uri.to_string());
+    hasher.update(b"\n");
+    hasher.update(content_type);
+    hasher.update(b"\n");
+    hasher.update(body);
+    crate::auth::to_hex(&hasher.finalize())
+}
+
+/// The answer to a request whose key was already claimed: the stored response
+/// if it was claimed by the same request, which has finished.
+fn replayed(stored: Option<IdempotencyRecord>, fingerprint: &str) -> Result<Response, AppError> {
+    let in_progress = || {
+        AppError(
+            StatusCode::CONFLICT,
+            "A request with this Idempotency-Key is in progress".to_string(),
+        )
+    };
+    // Expired or released since the claim failed
+    let stored = stored.ok_or_else(in_progress)?;
+    if stored.fingerprint != fingerprint {
+        return Err(AppError(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Idempotency-Key was already used for a different request".to_string(),
+        ));
+    }
+    let status = stored.status.ok_or_else(in_progress)?;
+
+    let mut response = Response::new(Body::from(stored.body.unwrap_or_default()));
+    *response.status_mut() =
+        StatusCode::from_u16(status as u16).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    if let Some(value) = stored
+        .content_type
+        .and_then(|t| HeaderValue::from_str(&t).ok())
+    {
+        response.headers_mut().insert(CONTENT_TYPE, value);
+    }
+    response
+        .headers_mut()
+        .insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(method: &str, uri: &str, content_type: Option<&str>) -> request::Parts {
+        let mut builder = Request::builder().method(method).uri(uri);
+        if let Some(content_type) = content_type {
+            builder = builder.header(CONTENT_TYPE, content_type);
+        }
+        builder.body(()).unwrap().into_parts().0
+    }
+
+    fn stored(fingerprint: &str, status: Option<i32>) -> Option<IdempotencyRecord> {
+        Some(IdempotencyRecord {
+            fingerprint: fingerprint.to_string(),
+            status,
+            content_type: status.map(|_| "application/json".to_string()),
+            body: status.map(|_| b"{\"id\":1}".to_vec()),
+        })
+    }
+
+    #[test]
+    fn fingerprint_covers_the_whole_request() {
+        let json = Some("application/json");
+        let base = fingerprint(&parts("POST", "/api/actions", json), b"{}");
+        assert_eq!(
+            base,
+            fingerprint(&parts("POST", "/api/actions", json), b"{}")
+        );
+        assert_ne!(
+            base,
+            fingerprint(&parts("PATCH", "/api/actions", json), b"{}")
+        );
+        assert_ne!(
+            base,
+            fingerprint(&parts("POST", "/api/actions?x=1", json), b"{}")
+        );
+        assert_ne!(
+            base,
+            fingerprint(&parts("POST", "/api/actions", None), b"{}")
+        );
+        let csv = Some("text/csv");
+        assert_ne!(
+            base,
+            fingerprint(&parts("POST", "/api/actions", csv), b"{}")
+        );
+        assert_ne!(
+            base,
+            fingerprint(&parts("POST", "/api/actions", json), b"{ }")
+        );
+    }
+
+    #[tokio::test]
+    async fn replays_the_stored_response() {
+        let response =
+            replayed(stored("abc", Some(201)), "abc").unwrap_or_else(|e| panic!("{}", e.1));
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+        assert_eq!(response.headers()[REPLAYED_HEADER], "true");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"{\"id\":1}");
+    }
+
+    #[test]
+    fn rejects_a_different_request() {
+        let result = replayed(stored("abc", Some(201)), "def");
+        assert!(matches!(
+            result,
+            Err(AppError(StatusCode::UNPROCESSABLE_ENTITY, _))
+        ));
+        // Even while the first one is running
+        let result = replayed(stored("abc", None), "def");
+        assert!(matches!(
+            result,
+            Err(AppError(StatusCode::UNPROCESSABLE_ENTITY, _))
+        ));
+    }
+
+    #[test]
+    fn rejects_a_retry_in_progress() {
+        let result = replayed(stored("abc", None), "abc");
+        assert!(matches!(result, Err(AppError(StatusCode::CONFLICT, _))));
+        let result = replayed(None, "abc");
+        assert!(matches!(result, Err(AppError(StatusCode::CONFLICT, _))));
+    }
+}
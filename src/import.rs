@@ -0,0 +1,475 @@
+//! Imports history from our own JSON export or from Loop Habit Tracker.
+//!
+//! Actions are matched by name with the user's existing actions outside of
+//! groups, or created. Rows are checked like completions made through the
+//! API: a record at the same time as an existing one is a duplicate, and no
+//! local day gets more records than the action's daily target, each taking
+//! one of the day's slots. Everything happens in one transaction, which a
+//! dry run rolls back.
+
+use axum::http::StatusCode;
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+use time::macros::format_description;
+use time::{Date, OffsetDateTime};
+
+use crate::db::{
+    create_imported_action, get_existing_import_records, get_import_targets,
+    insert_imported_records, resolve_import_times,
+};
+use crate::export::ARCHIVE_VERSION;
+use crate::models::{
+    normalize_tags, ImportAction, ImportArchive, ImportArchiveRecord, ImportFormat, ImportIssue,
+    ImportReport, ImportedRecord,
+};
+use crate::schedule::Schedule;
+use crate::AppError;
+
+/// Rows of each kind listed in the report.
+const MAX_REPORTED_ROWS: usize = 100;
+/// Loop's checkmark value for a day the habit was checked by hand; automatic
+/// checkmarks (1) only follow from the habit's frequency.
+const LOOP_CHECKED: &str = "2";
+
+enum Finish {
+    At(OffsetDateTime),
+    Day(Date), // placed at local noon
+}
+
+/// A row to import into `actions[action]`.
+struct Row {
+    row: usize,
+    action: usize,
+    finish: Finish,
+    note: Option<String>,
+    quantity: Option<i32>,
+    duration_seconds: Option<i32>,
+}
+
+struct Parsed {
+    actions: Vec<ImportAction>,
+    rows: Vec<Row>,
+    skipped: Vec<ImportIssue>,
+}
+
+pub async fn run(
+    pool: &PgPool,
+    user_id: i64,
+    format: ImportFormat,
+    body: &[u8],
+    dry_run: bool,
+) -> Result<ImportReport, AppError> {
+    let Parsed {
+        actions,
+        rows,
+        mut skipped,
+    } = match format {
+        ImportFormat::Json => parse_archive(body)?,
+        ImportFormat::Loop => parse_loop_checkmarks(body)?,
+    };
+    let mut report = ImportReport {
+        dry_run,
+        ..Default::default()
+    };
+
+    let mut tx = pool.begin().await?;
+
+    // (id, daily target) of each parsed action
+    let mut by_name: HashMap<String, (i64, i32)> = get_import_targets(&mut tx, user_id)
+        .await?
+        .into_iter()
+        .map(|t| (t.name, (t.id, t.daily_target)))
+        .collect();
+    let mut targets = Vec::with_capacity(actions.len());
+    for action in &actions {
+        let target = match by_name.get(&action.name) {
+            Some(&target) => {
+                report.actions_matched += 1;
+                target
+            }
+            None => {
+                let id = create_imported_action(&mut tx, user_id, action).await?;
+                report.actions_created += 1;
+                by_name.insert(action.name.clone(), (id, action.daily_target));
+                (id, action.daily_target)
+            }
+        };
+        targets.push(target);
+    }
+
+    let times: Vec<Option<OffsetDateTime>> = rows
+        .iter()
+        .map(|r| match r.finish {
+            Finish::At(time) => Some(time),
+            Finish::Day(_) => None,
+        })
+        .collect();
+    let days: Vec<Option<Date>> = rows
+        .iter()
+        .map(|r| match r.finish {
+            Finish::At(_) => None,
+            Finish::Day(day) => Some(day),
+        })
+        .collect();
+    let resolved = resolve_import_times(&mut tx, user_id, &times, &days).await?;
+
+    let action_ids: Vec<i64> = targets.iter().map(|&(id, _)| id).collect();
+    let mut seen = HashSet::new();
+    let mut taken_slots = HashSet::new();
+    for (action_id, finish_time, day, slot) in
+        get_existing_import_records(&mut tx, user_id, &action_ids).await?
+    {
+        seen.insert((action_id, finish_time));
+        taken_slots.insert((action_id, day, slot));
+    }
+
+    // Earlier rows fill a day first, like completions made one by one
+    let mut order: Vec<usize> = (0..rows.len()).collect();
+    order.sort_by_key(|&i| (resolved[i].0, rows[i].row));
+
+    let now = OffsetDateTime::now_utc();
+    let mut duplicates = Vec::new();
+    let mut records = Vec::new();
+    for i in order {
+        let row = &rows[i];
+        let (finish_time, day) = resolved[i];
+        let (action_id, daily_target) = targets[row.action];
+        let issue = |reason: String| ImportIssue {
+            row: row.row,
+            action: Some(actions[row.action].name.clone()),
+            reason,
+        };
+
+        if finish_time > now {
+            skipped.push(issue("Finish time is in the future".to_string()));
+            continue;
+        }
+        if !seen.insert((action_id, finish_time)) {
+            duplicates.push(issue("Already recorded at this time".to_string()));
+            continue;
+        }
+        let Some(day_slot) =
+            (1..=daily_target).find(|&slot| taken_slots.insert((action_id, day, slot)))
+        else {
+            skipped.push(issue(format!("Already completed on {}", day)));
+            continue;
+        };
+
+        records.push(ImportedRecord {
+            action_id,
+            finish_time,
+            local_day: day,
+            day_slot,
+            note: row.note.clone(),
+            quantity: row.quantity,
+            duration_seconds: row.duration_seconds,
+        });
+    }
+
+    if !records.is_empty() {
+        insert_imported_records(&mut tx, user_id, &records).await?;
+    }
+    if dry_run {
+        tx.rollback().await?;
+    } else {
+        tx.commit().await?;
+    }
+
+    report.records_imported = records.len() as i64;
+    report.duplicate_count = duplicates.len() as i64;
+    report.skipped_count = skipped.len() as i64;
+    report.duplicates = first_rows(duplicates);
+    report.skipped = first_rows(skipped);
+    Ok(report)
+}
+
+fn first_rows(mut issues: Vec<ImportIssue>) -> Vec<ImportIssue> {
+    issues.sort_by_key(|issue| issue.row);
+    issues.truncate(MAX_REPORTED_ROWS);
+    issues
+}
+
+fn bad_request(message: String) -> AppError {
+    AppError(StatusCode::BAD_REQUEST, message)
+}
+
+/// Reads an archive written by `GET /api/export?format=json`.
+fn parse_archive(body: &[u8]) -> Result<Parsed, AppError> {
+    let archive: ImportArchive =
+        serde_json::from_slice(body).map_err(|e| bad_request(format!("Invalid archive: {}", e)))?;
+    if archive.version != ARCHIVE_VERSION {
+        return Err(bad_request(format!(
+            "Unsupported archive version {}",
+            archive.version
+        )));
+    }
+
+    let mut actions = Vec::with_capacity(archive.actions.len());
+    let mut index = HashMap::new();
+    for action in archive.actions {
+        let name = action.name.trim().to_string();
+        if name.is_empty() {
+            return Err(bad_request(format!(
+                "Action {} has an empty name",
+                action.id
+            )));
+        }
+        let schedule = action.schedule.unwrap_or_default();
+        schedule
+            .validate()
+            .map_err(|e| bad_request(format!("Invalid schedule of '{}': {}", name, e)))?;
+        let daily_target = action.daily_target.unwrap_or(1);
+        if daily_target < 1 {
+            return Err(bad_request(format!(
+                "Daily target of '{}' must be at least 1",
+                name
+            )));
+        }
+        if index.insert(action.id, actions.len()).is_some() {
+            return Err(bad_request(format!("Action id {} repeats", action.id)));
+        }
+
+        actions.push(ImportAction {
+            name,
+            create_time: action.create_time,
+            archived: action.archived,
+            schedule,
+            daily_target,
+            unit: action.unit,
+            tags: normalize_tags(action.tags).map_err(bad_request)?,
+        });
+    }
+
+    let mut rows = Vec::with_capacity(archive.records.len());
+    let mut skipped = Vec::new();
+    for (i, value) in archive.records.into_iter().enumerate() {
+        let row = i + 1;
+        let record: ImportArchiveRecord = match serde_json::from_value(value) {
+            Ok(record) => record,
+            Err(e) => {
+                skipped.push(ImportIssue {
+                    row,
+                    action: None,
+                    reason: format!("Invalid record: {}", e),
+                });
+                continue;
+            }
+        };
+        let Some(&action) = index.get(&record.action_id) else {
+            skipped.push(ImportIssue {
+                row,
+                action: None,
+                reason: format!("Unknown action {}", record.action_id),
+            });
+            continue;
+        };
+
+        rows.push(Row {
+            row,
+            action,
+            finish: Finish::At(record.finish_time),
+            note: record.note,
+            quantity: record.quantity,
+            duration_seconds: record.duration_seconds,
+        });
+    }
+
+    Ok(Parsed {
+        actions,
+        rows,
+        skipped,
+    })
+}
+
+/// Reads `Checkmarks.csv` from the root of a Loop Habit Tracker export: a
+/// `Date` column followed by one column per habit. Each habit becomes a
+/// daily action, and each day checked by hand one record.
+fn parse_loop_checkmarks(body: &[u8]) -> Result<Parsed, AppError> {
+    let text = std::str::from_utf8(body)
+        .map_err(|_| bad_request("The CSV file must be UTF-8".to_string()))?;
+    let mut lines = parse_csv(text.trim_start_matches('\u{feff}')).into_iter();
+
+    let header = lines
+        .next()
+        .filter(|(_, fields)| fields.first().is_some_and(|f| f.trim() == "Date"))
+        .ok_or_else(|| {
+            bad_request("Expected Checkmarks.csv of a Loop Habit Tracker export".to_string())
+        })?
+        .1;
+
+    // Column of each habit, skipping the empty column of a trailing comma
+    let now = OffsetDateTime::now_utc();
+    let mut actions = Vec::new();
+    let mut columns = Vec::new();
+    for (column, name) in header.iter().enumerate().skip(1) {
+        let name = name.trim();
+        if name.is_empty() {
+            continue;
+        }
+        columns.push((column, actions.len()));
+        actions.push(ImportAction {
+            name: name.to_string(),
+            create_time: now,
+            archived: false,
+            schedule: Schedule::Daily,
+            daily_target: 1,
+            unit: None,
+            tags: Vec::new(),
+        });
+    }
+
+    let date_format = format_description!("[year]-[month]-[day]");
+    let mut rows = Vec::new();
+    let mut skipped = Vec::new();
+    for (line, fields) in lines {
+        if fields.iter().all(|f| f.trim().is_empty()) {
+            continue;
+        }
+        let Ok(day) = Date::parse(fields[0].trim(), &date_format) else {
+            skipped.push(ImportIssue {
+                row: line,
+                action: None,
+                reason: format!("Invalid date '{}'", fields[0].trim()),
+            });
+            continue;
+        };
+
+        for &(column, action) in &columns {
+            if fields.get(column).map(|f| f.trim()) == Some(LOOP_CHECKED) {
+                rows.push(Row {
+                    row: line,
+                    action,
+                    finish: Finish::Day(day),
+                    note: None,
+                    quantity: None,
+                    duration_seconds: None,
+                });
+            }
+        }
+    }
+
+    // Actions date from their first checkmark rather than the import
+    for row in &rows {
+        if let Finish::Day(day) = row.finish {
+            let start = day.midnight().assume_utc();
+            let action = &mut actions[row.action];
+            action.create_time = action.create_time.min(start);
+        }
+    }
+
+    Ok(Parsed {
+        actions,
+        rows,
+        skipped,
+    })
+}
+
+/// Splits RFC 4180 CSV into records, each with the line it starts on.
+fn parse_csv(text: &str) -> Vec<(usize, Vec<String>)> {
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut start = 1;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' if !quoted => {
+                fields.push(std::mem::take(&mut field));
+                records.push((start, std::mem::take(&mut fields)));
+                line += 1;
+                start = line;
+            }
+            c => {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+        }
+    }
+    if !field.is_empty() || !fields.is_empty() {
+        fields.push(field);
+        records.push((start, fields));
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::date;
+
+    fn fields(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_csv_splits_records_and_fields() {
+        let text = "a,b,c\r\n1,,3\r\n";
+        assert_eq!(
+            parse_csv(text),
+            vec![(1, fields(&["a", "b", "c"])), (2, fields(&["1", "", "3"]))]
+        );
+        // Without a final line break
+        assert_eq!(
+            parse_csv("a,b\nc"),
+            vec![(1, fields(&["a", "b"])), (2, fields(&["c"]))]
+        );
+        assert_eq!(parse_csv(""), Vec::new());
+    }
+
+    #[test]
+    fn parse_csv_reads_quoted_fields() {
+        // As written by the CSV export
+        let text =
+            "\"a,b\",\"say \"\"hi\"\"\",\"two\r\nlines\",\"\"\r\nnext,\"one\nmore\"\nlast\r\n";
+        assert_eq!(
+            parse_csv(text),
+            vec![
+                (1, fields(&["a,b", "say \"hi\"", "two\r\nlines", ""])),
+                (3, fields(&["next", "one\nmore"])),
+                (5, fields(&["last"])),
+            ]
+        );
+    }
+
+    #[test]
+    fn loop_checkmarks_skip_bom_and_trailing_comma() {
+        let text = "\u{feff}Date,Read,Run,\r\n\
+                    2024-03-02,2,0,\r\n\
+                    2024-03-01,1,2,\r\n\
+                    ,,,\r\n\
+                    yesterday,2,2,\r\n";
+        let parsed = match parse_loop_checkmarks(text.as_bytes()) {
+            Ok(parsed) => parsed,
+            Err(e) => panic!("{}", e.1),
+        };
+
+        let names: Vec<&str> = parsed.actions.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, ["Read", "Run"]);
+        let rows: Vec<(usize, usize)> = parsed.rows.iter().map(|r| (r.row, r.action)).collect();
+        assert_eq!(rows, [(2, 0), (3, 1)]);
+        assert!(matches!(parsed.rows[0].finish, Finish::Day(day) if day == date!(2024 - 03 - 02)));
+        // Actions date from their first checkmark
+        assert_eq!(parsed.actions[1].create_time.date(), date!(2024 - 03 - 01));
+        assert_eq!(parsed.skipped.len(), 1);
+        assert_eq!(parsed.skipped[0].row, 5);
+    }
+
+    #[test]
+    fn loop_checkmarks_need_a_date_column() {
+        assert!(parse_loop_checkmarks(b"Day,Read\r\n2024-03-01,2\r\n").is_err());
+        assert!(parse_loop_checkmarks(b"Date,Read\r\n\xff\r\n").is_err());
+    }
+}
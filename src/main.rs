@@ -1,42 +1,116 @@
 mod auth;
 mod db;
+mod export;
+mod idempotency;
+mod import;
+mod migrate;
 mod models;
+mod notifier;
+mod reminder;
+mod schedule;
+mod streak;
+mod sync;
+mod webhook;
 
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
+    body::{Body, Bytes},
+    extract::{DefaultBodyLimit, Path, Query, State},
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+        StatusCode,
+    },
+    middleware,
     response::{IntoResponse, Response},
-    routing::{get, post},
+    routing::{delete, get, patch, post, put},
     Json, Router,
 };
 use dotenv::dotenv;
 use reqwest::{header, Client};
+use serde::de::DeserializeOwned;
 use serde_json::json;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::env;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use time::OffsetDateTime;
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::trace::{self, TraceLayer};
-use tracing::{error, info, Level};
+use tracing::{error, info, warn, Level};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
 
-use crate::auth::AuthUser;
+use crate::auth::{AuthUser, JwtKeys};
 use crate::db::{
-    can_finish_today, create_practice_action, create_practice_record, create_user,
-    get_practice_action, get_practice_records, get_user_by_username, list_actions_with_stats,
+    add_group_member, check_finish_time, create_practice_action, create_practice_group,
+    create_practice_record, create_refresh_token, create_reminder, create_user, create_user_tag,
+    create_user_webhook, delete_practice_action, delete_practice_group, delete_practice_record,
+    delete_reminder, delete_user_tag, delete_user_webhook, get_action_performance, get_active_days,
+    get_completion_counts, get_group_members, get_group_role, get_leaderboard_entries,
+    get_practice_action, get_practice_calendar, get_practice_records, get_reminders,
+    get_user_by_id, get_user_by_username, get_webhook_deliveries, is_valid_time_zone,
+    list_actions_with_stats, list_tags_with_stats, list_user_groups, list_user_webhooks,
+    local_today, remove_group_member, rename_practice_group, rename_user_tag,
+    reorder_practice_actions, revoke_access_token, revoke_all_user_tokens,
+    revoke_refresh_token_family, rotate_refresh_token, update_group_member_role,
+    update_practice_action, update_practice_record, update_user_email, update_user_time_zone,
+    update_user_webhook,
 };
 use crate::models::{
-    CreateActionRequest, LoginRequest, LoginResponse, PracticeAction, PracticeRecord, QueryParams,
-    RegisterRequest,
+    ActionWithStats, AddMemberRequest, CalendarQuery, CalendarResponse, CreateActionRequest,
+    CreateReminderRequest, CreateWebhookRequest, CreateWebhookResponse, DeliveriesQuery,
+    ExportQuery, FinishActionRequest, FinishOutcome, Group, GroupDetails, GroupMember,
+    GroupRequest, GroupRole, ImportQuery, ImportReport, LeaderboardQuery, LeaderboardResponse,
+    ListActionsQuery, LoginRequest, LoginResponse, OverviewQuery, OverviewResponse,
+    OverviewStreaks, PracticeAction, PracticeRecord, QueryParams, RecordCursor, RecordPage,
+    RecordsQuery, RefreshOutcome, RefreshRequest, RegisterRequest, Reminder, ReorderActionsRequest,
+    ReorderOutcome, SyncChanges, SyncPushRequest, SyncPushResponse, SyncQuery, Tag, TagRequest,
+    TagWithStats, TodayOverview, TokenResponse, UpdateActionRequest, UpdateMemberRequest,
+    UpdateProfileRequest, UpdateRecordRequest, UpdateWebhookRequest, User, Webhook,
+    WebhookDelivery, WebhookEvent,
 };
+use crate::schedule::Schedule;
 
 pub struct AppState {
     pub pool: sqlx::PgPool,
+    pub jwt_keys: JwtKeys,
 }
 
+lazy_static::lazy_static! {
+    /// How many days back a completion may be recorded, 0 disables backfilling.
+    static ref BACKFILL_DAYS: u32 = env::var("BACKFILL_DAYS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(7);
+    /// How often the reminder task looks for due reminders.
+    static ref REMINDER_INTERVAL_SECS: u64 = env::var("REMINDER_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&secs| secs > 0)
+        .unwrap_or(60);
+    /// How often queued webhook events are delivered.
+    static ref WEBHOOK_INTERVAL_SECS: u64 = env::var("WEBHOOK_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&secs| secs > 0)
+        .unwrap_or(5);
+    /// How long responses to requests with an Idempotency-Key are kept.
+    static ref IDEMPOTENCY_TTL_SECS: i64 = env::var("IDEMPOTENCY_TTL_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&secs| secs > 0)
+        .unwrap_or(24 * 60 * 60);
+}
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+const MAX_OVERVIEW_DAYS: i64 = 5 * 366;
+const OVERVIEW_TOP_ACTIONS: usize = 3;
+const MAX_IMPORT_BYTES: usize = 32 * 1024 * 1024;
+/// axum's default limit, used for every other request body.
+const MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
 pub struct AppError(StatusCode, String);
 
 impl IntoResponse for AppError {
@@ -79,6 +153,10 @@ pub async fn register_user(
     State(state): State<Arc<AppState>>,
     Json(req): Json<RegisterRequest>,
 ) -> Result<Json<LoginResponse>, AppError> {
+    // Checked first, so that invalid input does not pay for hashing
+    let time_zone = req.time_zone.as_deref().unwrap_or("UTC");
+    validate_time_zone(&state.pool, time_zone).await?;
+
     let password_hash = crate::auth::hash_password(&req.password).map_err(|_| {
         AppError(
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -86,16 +164,16 @@ pub async fn register_user(
         )
     })?;
 
-    let user = create_user(&state.pool, &req.username, &password_hash).await?;
+    let user = create_user(&state.pool, &req.username, &password_hash, time_zone).await?;
 
-    let token = crate::auth::create_token(user.id).map_err(|_| {
-        AppError(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Failed to create token".to_string(),
-        )
-    })?;
+    let tokens = issue_tokens(&state, &user).await?;
 
-    Ok(Json(LoginResponse { token, user }))
+    Ok(Json(LoginResponse {
+        token: tokens.token,
+        refresh_token: tokens.refresh_token,
+        expires_in: tokens.expires_in,
+        user,
+    }))
 }
 
 pub async fn login_user(
@@ -113,73 +191,1036 @@ pub async fn login_user(
         ));
     }
 
-    let token = crate::auth::create_token(user.id).map_err(|_| {
+    let tokens = issue_tokens(&state, &user).await?;
+
+    Ok(Json(LoginResponse {
+        token: tokens.token,
+        refresh_token: tokens.refresh_token,
+        expires_in: tokens.expires_in,
+        user,
+    }))
+}
+
+pub async fn refresh_token(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<RefreshRequest>,
+) -> Result<Json<TokenResponse>, AppError> {
+    let token_hash = crate::auth::hash_refresh_token(&req.refresh_token);
+    let (refresh_token, new_token_hash) = crate::auth::generate_refresh_token();
+    let expire_time = OffsetDateTime::now_utc() + *crate::auth::REFRESH_TOKEN_TTL;
+
+    let rotated =
+        match rotate_refresh_token(&state.pool, &token_hash, &new_token_hash, expire_time).await? {
+            RefreshOutcome::Rotated(rotated) => rotated,
+            RefreshOutcome::Reused => {
+                warn!("Refresh token reuse detected, token family revoked");
+                return Err(AppError(
+                    StatusCode::UNAUTHORIZED,
+                    "Refresh token reuse detected".to_string(),
+                ));
+            }
+            RefreshOutcome::Invalid => {
+                return Err(AppError(
+                    StatusCode::UNAUTHORIZED,
+                    "Invalid refresh token".to_string(),
+                ))
+            }
+        };
+
+    let user = get_user_by_id(&state.pool, rotated.user_id)
+        .await?
+        .ok_or_else(|| {
+            AppError(
+                StatusCode::UNAUTHORIZED,
+                "Invalid refresh token".to_string(),
+            )
+        })?;
+    let token = create_access_token(&state.jwt_keys, &user, rotated.family_id)?;
+
+    Ok(Json(TokenResponse {
+        token,
+        refresh_token,
+        expires_in: crate::auth::ACCESS_TOKEN_TTL.whole_seconds(),
+    }))
+}
+
+/// Starts a new session: an access token plus the first refresh token of a
+/// new rotation family.
+async fn issue_tokens(state: &AppState, user: &User) -> Result<TokenResponse, AppError> {
+    let session_id = Uuid::new_v4();
+    let token = create_access_token(&state.jwt_keys, user, session_id)?;
+
+    let (refresh_token, token_hash) = crate::auth::generate_refresh_token();
+    let expire_time = OffsetDateTime::now_utc() + *crate::auth::REFRESH_TOKEN_TTL;
+    create_refresh_token(&state.pool, user.id, session_id, &token_hash, expire_time).await?;
+
+    Ok(TokenResponse {
+        token,
+        refresh_token,
+        expires_in: crate::auth::ACCESS_TOKEN_TTL.whole_seconds(),
+    })
+}
+
+fn create_access_token(keys: &JwtKeys, user: &User, session_id: Uuid) -> Result<String, AppError> {
+    crate::auth::create_token(keys, user.id, user.token_version, session_id).map_err(|_| {
         AppError(
             StatusCode::INTERNAL_SERVER_ERROR,
             "Failed to create token".to_string(),
         )
-    })?;
+    })
+}
+
+/// Ends the current session: the presented access token is revoked and the
+/// refresh tokens issued with it can no longer be exchanged.
+pub async fn logout(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, AppError> {
+    revoke_access_token(
+        &state.pool,
+        auth_user.user_id,
+        auth_user.jti,
+        auth_user.expire_time,
+    )
+    .await?;
+    revoke_refresh_token_family(&state.pool, auth_user.user_id, auth_user.session_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Ends every session of the current user, e.g. after losing a device.
+pub async fn logout_all(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, AppError> {
+    revoke_all_user_tokens(&state.pool, auth_user.user_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
 
-    Ok(Json(LoginResponse { token, user }))
+pub async fn get_profile(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<User>, AppError> {
+    let user = get_user_by_id(&state.pool, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError(StatusCode::NOT_FOUND, "User not found".to_string()))?;
+    Ok(Json(user))
+}
+
+pub async fn update_profile(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<UpdateProfileRequest>,
+) -> Result<Json<User>, AppError> {
+    let mut user = get_user_by_id(&state.pool, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError(StatusCode::NOT_FOUND, "User not found".to_string()))?;
+
+    if let Some(time_zone) = req.time_zone {
+        validate_time_zone(&state.pool, &time_zone).await?;
+        user = update_user_time_zone(&state.pool, auth_user.user_id, &time_zone).await?;
+    }
+    if let Some(email) = req.email {
+        let email = email.map(|email| email.trim().to_string());
+        if let Some(email) = &email {
+            validate_email(email)?;
+        }
+        user = update_user_email(&state.pool, auth_user.user_id, email.as_deref()).await?;
+    }
+
+    Ok(Json(user))
+}
+
+async fn validate_time_zone(pool: &sqlx::PgPool, time_zone: &str) -> Result<(), AppError> {
+    if !is_valid_time_zone(pool, time_zone).await? {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            format!("Unknown time zone: {}", time_zone),
+        ));
+    }
+    Ok(())
+}
+
+/// A plain sanity check; deliverability is up to the mail server.
+fn validate_email(email: &str) -> Result<(), AppError> {
+    let valid = email
+        .split_once('@')
+        .is_some_and(|(local, domain)| !local.is_empty() && domain.contains('.'))
+        && !email.chars().any(|c| c.is_whitespace() || c.is_control());
+    if !valid {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid email: {}", email),
+        ));
+    }
+    Ok(())
 }
 
 pub async fn create_action(
     auth_user: AuthUser,
     State(state): State<Arc<AppState>>,
-    Json(req): Json<CreateActionRequest>,
+    Json(mut req): Json<CreateActionRequest>,
 ) -> Result<Json<PracticeAction>, AppError> {
     println!("create action req: {:#?} userId {}", req, auth_user.user_id);
-    let action = create_practice_action(&state.pool, auth_user.user_id, req.name).await?;
+    req.tags = req.tags.map(normalize_tags).transpose()?;
+    if let Some(schedule) = &req.schedule {
+        validate_schedule(schedule)?;
+    }
+    if let Some(daily_target) = req.daily_target {
+        validate_daily_target(daily_target)?;
+    }
+    if let Some(group_id) = req.group_id {
+        require_group_role(&state.pool, auth_user.user_id, group_id).await?;
+    }
+    let action = create_practice_action(&state.pool, auth_user.user_id, req).await?;
     Ok(Json(action))
 }
 
+fn validate_schedule(schedule: &Schedule) -> Result<(), AppError> {
+    schedule
+        .validate()
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, format!("Invalid schedule: {}", e)))
+}
+
+fn validate_daily_target(daily_target: i32) -> Result<(), AppError> {
+    models::validate_daily_target(daily_target).map_err(|e| AppError(StatusCode::BAD_REQUEST, e))
+}
+
+fn normalize_tag_name(name: &str) -> Result<String, AppError> {
+    models::normalize_tag_name(name).map_err(|e| AppError(StatusCode::BAD_REQUEST, e))
+}
+
+fn normalize_tags(tags: Vec<String>) -> Result<Vec<String>, AppError> {
+    models::normalize_tags(tags).map_err(|e| AppError(StatusCode::BAD_REQUEST, e))
+}
+
 pub async fn list_actions(
     auth_user: AuthUser,
     State(state): State<Arc<AppState>>,
-) -> Result<Json<Vec<crate::models::ActionWithStats>>, AppError> {
-    let actions = list_actions_with_stats(&state.pool, auth_user.user_id).await?;
+    Query(query): Query<ListActionsQuery>,
+) -> Result<Json<Vec<ActionWithStats>>, AppError> {
+    let include_archived = query.include_archived.unwrap_or(false);
+    let actions = list_actions_with_stats(
+        &state.pool,
+        auth_user.user_id,
+        None,
+        include_archived,
+        query.tag.as_deref(),
+        query.sort,
+    )
+    .await?;
     Ok(Json(actions))
 }
 
+pub async fn reorder_actions(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<ReorderActionsRequest>,
+) -> Result<StatusCode, AppError> {
+    let mut ids = req.ids.clone();
+    ids.sort_unstable();
+    ids.dedup();
+    if ids.len() != req.ids.len() {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            "Action ids must not repeat".to_string(),
+        ));
+    }
+
+    match reorder_practice_actions(&state.pool, auth_user.user_id, &req.ids).await? {
+        ReorderOutcome::Reordered => Ok(StatusCode::NO_CONTENT),
+        ReorderOutcome::NotFound => Err(AppError(
+            StatusCode::NOT_FOUND,
+            "Action not found".to_string(),
+        )),
+        ReorderOutcome::GroupAction => Err(AppError(
+            StatusCode::BAD_REQUEST,
+            "Group actions have no manual order".to_string(),
+        )),
+    }
+}
+
 pub async fn get_action(
     auth_user: AuthUser,
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
-) -> Result<Json<Option<PracticeAction>>, AppError> {
-    let action = get_practice_action(&state.pool, auth_user.user_id, id).await?;
+) -> Result<Json<Option<ActionWithStats>>, AppError> {
+    let action =
+        list_actions_with_stats(&state.pool, auth_user.user_id, Some(id), true, None, None)
+            .await?
+            .pop();
     Ok(Json(action))
 }
 
-pub async fn finish_action(
+pub async fn update_action(
     auth_user: AuthUser,
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
-) -> Result<Json<PracticeRecord>, AppError> {
-    // Check if action exists and belongs to user
-    let action = get_practice_action(&state.pool, auth_user.user_id, id)
+    Json(mut req): Json<UpdateActionRequest>,
+) -> Result<Json<PracticeAction>, AppError> {
+    if req
+        .name
+        .as_deref()
+        .is_some_and(|name| name.trim().is_empty())
+    {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            "Name must not be empty".to_string(),
+        ));
+    }
+
+    if let Some(schedule) = &req.schedule {
+        validate_schedule(schedule)?;
+    }
+    if let Some(daily_target) = req.daily_target {
+        validate_daily_target(daily_target)?;
+    }
+    req.tags = req.tags.map(normalize_tags).transpose()?;
+
+    let action = update_practice_action(&state.pool, auth_user.user_id, id, req)
+        .await?
+        .ok_or_else(|| AppError(StatusCode::NOT_FOUND, "Action not found".to_string()))?;
+    Ok(Json(action))
+}
+
+pub async fn delete_action(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    if !delete_practice_action(&state.pool, auth_user.user_id, id).await? {
+        return Err(AppError(
+            StatusCode::NOT_FOUND,
+            "Action not found".to_string(),
+        ));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn list_action_reminders(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<Json<Vec<Reminder>>, AppError> {
+    get_practice_action(&state.pool, auth_user.user_id, id)
+        .await?
+        .ok_or_else(|| AppError(StatusCode::NOT_FOUND, "Action not found".to_string()))?;
+    let reminders = get_reminders(&state.pool, auth_user.user_id, id).await?;
+    Ok(Json(reminders))
+}
+
+pub async fn add_action_reminder(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Json(req): Json<CreateReminderRequest>,
+) -> Result<Json<Reminder>, AppError> {
+    get_practice_action(&state.pool, auth_user.user_id, id)
         .await?
         .ok_or_else(|| AppError(StatusCode::NOT_FOUND, "Action not found".to_string()))?;
+    let reminder = create_reminder(&state.pool, auth_user.user_id, id, req.remind_at).await?;
+    Ok(Json(reminder))
+}
+
+pub async fn remove_reminder(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    if !delete_reminder(&state.pool, auth_user.user_id, id).await? {
+        return Err(AppError(
+            StatusCode::NOT_FOUND,
+            "Reminder not found".to_string(),
+        ));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
 
-    // Check if already completed today
-    if !can_finish_today(&state.pool, auth_user.user_id, action.id).await? {
+fn validate_webhook_url(url: &str) -> Result<(), AppError> {
+    webhook::validate_url(url).map_err(|e| {
+        AppError(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid webhook URL {}: {}", url, e),
+        )
+    })
+}
+
+/// Event names to store, without duplicates.
+fn webhook_event_names(events: &[WebhookEvent]) -> Result<Vec<&'static str>, AppError> {
+    let mut names: Vec<&'static str> = events.iter().map(WebhookEvent::as_str).collect();
+    names.sort_unstable();
+    names.dedup();
+    if names.is_empty() {
         return Err(AppError(
-            StatusCode::CONFLICT,
-            "Already completed today".to_string(),
+            StatusCode::BAD_REQUEST,
+            "At least one event is required".to_string(),
         ));
     }
+    Ok(names)
+}
 
-    let note = Some(String::new());
-    let record = create_practice_record(&state.pool, auth_user.user_id, action.id, note).await?;
+pub async fn create_webhook(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<CreateWebhookRequest>,
+) -> Result<Json<CreateWebhookResponse>, AppError> {
+    validate_webhook_url(&req.url)?;
+    let events = webhook_event_names(&req.events)?;
+    let secret = match req.secret {
+        Some(secret) if secret.len() < 16 => {
+            return Err(AppError(
+                StatusCode::BAD_REQUEST,
+                "Webhook secret must be at least 16 characters".to_string(),
+            ))
+        }
+        Some(secret) => secret,
+        None => webhook::generate_secret(),
+    };
+
+    let webhook =
+        create_user_webhook(&state.pool, auth_user.user_id, &req.url, &secret, &events).await?;
+    Ok(Json(CreateWebhookResponse { webhook, secret }))
+}
+
+pub async fn list_webhooks(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<Webhook>>, AppError> {
+    let webhooks = list_user_webhooks(&state.pool, auth_user.user_id).await?;
+    Ok(Json(webhooks))
+}
+
+pub async fn update_webhook(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Json(req): Json<UpdateWebhookRequest>,
+) -> Result<Json<Webhook>, AppError> {
+    if let Some(url) = &req.url {
+        validate_webhook_url(url)?;
+    }
+    let events = req.events.as_deref().map(webhook_event_names).transpose()?;
+
+    let webhook = update_user_webhook(
+        &state.pool,
+        auth_user.user_id,
+        id,
+        req.url.as_deref(),
+        events.as_deref(),
+        req.active,
+    )
+    .await?
+    .ok_or_else(|| AppError(StatusCode::NOT_FOUND, "Webhook not found".to_string()))?;
+    Ok(Json(webhook))
+}
+
+pub async fn delete_webhook(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    if !delete_user_webhook(&state.pool, auth_user.user_id, id).await? {
+        return Err(AppError(
+            StatusCode::NOT_FOUND,
+            "Webhook not found".to_string(),
+        ));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn list_webhook_deliveries(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Query(query): Query<DeliveriesQuery>,
+) -> Result<Json<Vec<WebhookDelivery>>, AppError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let deliveries = get_webhook_deliveries(
+        &state.pool,
+        auth_user.user_id,
+        id,
+        query.status,
+        query.before,
+        limit,
+    )
+    .await?;
+    Ok(Json(deliveries))
+}
+
+pub async fn create_tag(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<TagRequest>,
+) -> Result<Json<Tag>, AppError> {
+    let name = normalize_tag_name(&req.name)?;
+    let tag = create_user_tag(&state.pool, auth_user.user_id, &name).await?;
+    Ok(Json(tag))
+}
+
+pub async fn list_tags(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<TagWithStats>>, AppError> {
+    let tags = list_tags_with_stats(&state.pool, auth_user.user_id, None).await?;
+    Ok(Json(tags))
+}
+
+pub async fn get_tag(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<Json<TagWithStats>, AppError> {
+    let tag = list_tags_with_stats(&state.pool, auth_user.user_id, Some(id))
+        .await?
+        .pop()
+        .ok_or_else(|| AppError(StatusCode::NOT_FOUND, "Tag not found".to_string()))?;
+    Ok(Json(tag))
+}
+
+pub async fn update_tag(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Json(req): Json<TagRequest>,
+) -> Result<Json<Tag>, AppError> {
+    let name = normalize_tag_name(&req.name)?;
+    let tag = rename_user_tag(&state.pool, auth_user.user_id, id, &name)
+        .await?
+        .ok_or_else(|| AppError(StatusCode::NOT_FOUND, "Tag not found".to_string()))?;
+    Ok(Json(tag))
+}
+
+pub async fn delete_tag(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    if !delete_user_tag(&state.pool, auth_user.user_id, id).await? {
+        return Err(AppError(StatusCode::NOT_FOUND, "Tag not found".to_string()));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn normalize_group_name(name: &str) -> Result<String, AppError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            "Group name must not be empty".to_string(),
+        ));
+    }
+    Ok(name.to_string())
+}
+
+/// The user's role in the group; groups the user is not a member of are
+/// reported as missing.
+async fn require_group_role(
+    pool: &sqlx::PgPool,
+    user_id: i64,
+    group_id: i64,
+) -> Result<GroupRole, AppError> {
+    get_group_role(pool, user_id, group_id)
+        .await?
+        .ok_or_else(|| AppError(StatusCode::NOT_FOUND, "Group not found".to_string()))
+}
+
+pub async fn create_group(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<GroupRequest>,
+) -> Result<Json<Group>, AppError> {
+    let name = normalize_group_name(&req.name)?;
+    let group = create_practice_group(&state.pool, auth_user.user_id, &name).await?;
+    Ok(Json(group))
+}
+
+pub async fn list_groups(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<Group>>, AppError> {
+    let groups = list_user_groups(&state.pool, auth_user.user_id, None).await?;
+    Ok(Json(groups))
+}
+
+pub async fn get_group(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<Json<GroupDetails>, AppError> {
+    let group = list_user_groups(&state.pool, auth_user.user_id, Some(id))
+        .await?
+        .pop()
+        .ok_or_else(|| AppError(StatusCode::NOT_FOUND, "Group not found".to_string()))?;
+    let members = get_group_members(&state.pool, id).await?;
+    Ok(Json(GroupDetails { group, members }))
+}
+
+pub async fn update_group(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Json(req): Json<GroupRequest>,
+) -> Result<Json<Group>, AppError> {
+    let name = normalize_group_name(&req.name)?;
+    if !require_group_role(&state.pool, auth_user.user_id, id)
+        .await?
+        .can_manage()
+    {
+        return Err(AppError(
+            StatusCode::FORBIDDEN,
+            "Only owners and admins can rename the group".to_string(),
+        ));
+    }
+    rename_practice_group(&state.pool, id, &name).await?;
+    let group = list_user_groups(&state.pool, auth_user.user_id, Some(id))
+        .await?
+        .pop()
+        .ok_or_else(|| AppError(StatusCode::NOT_FOUND, "Group not found".to_string()))?;
+    Ok(Json(group))
+}
+
+pub async fn delete_group(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    if require_group_role(&state.pool, auth_user.user_id, id).await? != GroupRole::Owner {
+        return Err(AppError(
+            StatusCode::FORBIDDEN,
+            "Only the owner can delete the group".to_string(),
+        ));
+    }
+    delete_practice_group(&state.pool, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn add_member(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Json(req): Json<AddMemberRequest>,
+) -> Result<Json<GroupMember>, AppError> {
+    if !require_group_role(&state.pool, auth_user.user_id, id)
+        .await?
+        .can_manage()
+    {
+        return Err(AppError(
+            StatusCode::FORBIDDEN,
+            "Only owners and admins can add members".to_string(),
+        ));
+    }
+    let role = req.role.unwrap_or(GroupRole::Member);
+    if role == GroupRole::Owner {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            "A group has only one owner".to_string(),
+        ));
+    }
+
+    let user = get_user_by_username(&state.pool, req.username.trim())
+        .await?
+        .ok_or_else(|| AppError(StatusCode::NOT_FOUND, "User not found".to_string()))?;
+    let member = add_group_member(&state.pool, id, user.id, role)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(ref db) if db.is_unique_violation() => {
+                AppError(StatusCode::CONFLICT, "User is already a member".to_string())
+            }
+            e => e.into(),
+        })?;
+    Ok(Json(member))
+}
+
+pub async fn update_member(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Path((id, user_id)): Path<(i64, i64)>,
+    Json(req): Json<UpdateMemberRequest>,
+) -> Result<Json<GroupMember>, AppError> {
+    if !require_group_role(&state.pool, auth_user.user_id, id)
+        .await?
+        .can_manage()
+    {
+        return Err(AppError(
+            StatusCode::FORBIDDEN,
+            "Only owners and admins can change roles".to_string(),
+        ));
+    }
+    if req.role == GroupRole::Owner {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            "A group has only one owner".to_string(),
+        ));
+    }
+
+    let member = update_group_member_role(&state.pool, id, user_id, req.role)
+        .await?
+        .ok_or_else(|| AppError(StatusCode::NOT_FOUND, "Member not found".to_string()))?;
+    Ok(Json(member))
+}
+
+/// Removes a member; any member may also remove themselves to leave. The
+/// owner cannot leave and deletes the group instead.
+pub async fn remove_member(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Path((id, user_id)): Path<(i64, i64)>,
+) -> Result<StatusCode, AppError> {
+    let role = require_group_role(&state.pool, auth_user.user_id, id).await?;
+    if user_id == auth_user.user_id && role == GroupRole::Owner {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            "The owner cannot leave the group".to_string(),
+        ));
+    }
+    if user_id != auth_user.user_id && !role.can_manage() {
+        return Err(AppError(
+            StatusCode::FORBIDDEN,
+            "Only owners and admins can remove members".to_string(),
+        ));
+    }
+
+    if !remove_group_member(&state.pool, id, user_id).await? {
+        return Err(AppError(
+            StatusCode::NOT_FOUND,
+            "Member not found".to_string(),
+        ));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn get_group_leaderboard(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Query(query): Query<LeaderboardQuery>,
+) -> Result<Json<LeaderboardResponse>, AppError> {
+    require_group_role(&state.pool, auth_user.user_id, id).await?;
+
+    let to = match query.to {
+        Some(to) => to,
+        None => local_today(&state.pool, auth_user.user_id).await?,
+    };
+    let from = query.from.unwrap_or(to - time::Duration::days(29));
+    if from > to {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            "'from' must not be after 'to'".to_string(),
+        ));
+    }
+    if to - from > time::Duration::days(MAX_OVERVIEW_DAYS) {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            format!("The window must not exceed {} days", MAX_OVERVIEW_DAYS),
+        ));
+    }
+
+    let entries = get_leaderboard_entries(&state.pool, id, from, to).await?;
+    Ok(Json(LeaderboardResponse { from, to, entries }))
+}
+
+pub async fn finish_action(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    body: Bytes,
+) -> Result<Json<PracticeRecord>, AppError> {
+    let req: FinishActionRequest = parse_optional_json(&body)?;
+
+    let backfill = req.finish_time.is_some();
+    if let Some(finish_time) = req.finish_time {
+        if *BACKFILL_DAYS == 0 {
+            return Err(AppError(
+                StatusCode::BAD_REQUEST,
+                "Backfilling is disabled".to_string(),
+            ));
+        }
+        let pool = &state.pool;
+        if let Some(reason) =
+            check_finish_time(pool, auth_user.user_id, finish_time, *BACKFILL_DAYS).await?
+        {
+            return Err(AppError(StatusCode::BAD_REQUEST, reason));
+        }
+    }
+
+    // Checks and records the completion in one transaction
+    match create_practice_record(&state.pool, auth_user.user_id, id, req, None).await? {
+        FinishOutcome::Finished(record) => Ok(Json(record)),
+        FinishOutcome::NotFound => Err(AppError(
+            StatusCode::NOT_FOUND,
+            "Action not found".to_string(),
+        )),
+        FinishOutcome::Archived => Err(AppError(
+            StatusCode::CONFLICT,
+            "Action is archived".to_string(),
+        )),
+        FinishOutcome::TargetReached => {
+            let message = if backfill {
+                "Already completed on that day"
+            } else {
+                "Already completed today"
+            };
+            Err(AppError(StatusCode::CONFLICT, message.to_string()))
+        }
+    }
+}
+
+pub async fn update_record(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Json(req): Json<UpdateRecordRequest>,
+) -> Result<Json<PracticeRecord>, AppError> {
+    let record = update_practice_record(&state.pool, auth_user.user_id, id, req)
+        .await?
+        .ok_or_else(|| AppError(StatusCode::NOT_FOUND, "Record not found".to_string()))?;
     Ok(Json(record))
 }
 
+pub async fn delete_record(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    if !delete_practice_record(&state.pool, auth_user.user_id, id).await? {
+        return Err(AppError(
+            StatusCode::NOT_FOUND,
+            "Record not found".to_string(),
+        ));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Parses a JSON body that clients may leave out entirely.
+fn parse_optional_json<T: DeserializeOwned + Default>(body: &[u8]) -> Result<T, AppError> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(T::default());
+    }
+    serde_json::from_slice(body)
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, format!("Invalid JSON body: {}", e)))
+}
+
 pub async fn get_action_records(
     auth_user: AuthUser,
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
-) -> Result<Json<Vec<PracticeRecord>>, AppError> {
-    let records = get_practice_records(&state.pool, auth_user.user_id, id).await?;
-    Ok(Json(records))
+    Query(query): Query<RecordsQuery>,
+) -> Result<Json<RecordPage>, AppError> {
+    let cursor = match query.cursor.as_deref() {
+        Some(cursor) => Some(
+            RecordCursor::decode(cursor)
+                .ok_or_else(|| AppError(StatusCode::BAD_REQUEST, "Invalid cursor".to_string()))?,
+        ),
+        None => None,
+    };
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    // One extra row tells whether another page follows
+    let mut records = get_practice_records(
+        &state.pool,
+        auth_user.user_id,
+        id,
+        cursor,
+        query.from,
+        query.to,
+        limit + 1,
+    )
+    .await?;
+
+    let next_cursor = if records.len() as i64 > limit {
+        records.truncate(limit as usize);
+        records.last().map(|last| {
+            RecordCursor {
+                finish_time: last.finish_time,
+                id: last.id,
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+
+    Ok(Json(RecordPage {
+        records,
+        next_cursor,
+    }))
+}
+
+pub async fn get_action_calendar(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Query(query): Query<CalendarQuery>,
+) -> Result<Json<CalendarResponse>, AppError> {
+    let action = get_practice_action(&state.pool, auth_user.user_id, id)
+        .await?
+        .ok_or_else(|| AppError(StatusCode::NOT_FOUND, "Action not found".to_string()))?;
+
+    // Defaults to the last year up to today, like a contribution heatmap
+    let to = match query.to {
+        Some(to) => to,
+        None => local_today(&state.pool, auth_user.user_id).await?,
+    };
+    let from = query.from.unwrap_or(to - time::Duration::days(364));
+    if from > to {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            "'from' must not be after 'to'".to_string(),
+        ));
+    }
+
+    let days = get_practice_calendar(&state.pool, auth_user.user_id, action.id, from, to).await?;
+    Ok(Json(CalendarResponse {
+        action_id: action.id,
+        from,
+        to,
+        days,
+    }))
+}
+
+pub async fn get_stats_overview(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<OverviewQuery>,
+) -> Result<Json<OverviewResponse>, AppError> {
+    let today = local_today(&state.pool, auth_user.user_id).await?;
+    let to = query.to.unwrap_or(today);
+    let from = query.from.unwrap_or(to - time::Duration::days(29));
+    if from > to {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            "'from' must not be after 'to'".to_string(),
+        ));
+    }
+    if to - from > time::Duration::days(MAX_OVERVIEW_DAYS) {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            format!("The window must not exceed {} days", MAX_OVERVIEW_DAYS),
+        ));
+    }
+    let granularity = query.granularity.unwrap_or_default();
+
+    // Today's figures come from the same per-action stats as the action list
+    let actions =
+        list_actions_with_stats(&state.pool, auth_user.user_id, None, false, None, None).await?;
+    let relevant: Vec<&ActionWithStats> = actions
+        .iter()
+        .filter(|a| a.paused_until.filter(|until| *until >= today).is_none())
+        .filter(|a| a.due || a.finished_today)
+        .collect();
+    let completed = relevant.iter().filter(|a| a.finished_today).count() as i64;
+    let active_actions = relevant.len() as i64;
+
+    let completions =
+        get_completion_counts(&state.pool, auth_user.user_id, from, to, granularity).await?;
+
+    // Ordered best first; with few actions the two lists overlap
+    let performance = get_action_performance(&state.pool, auth_user.user_id, from, to).await?;
+    let worst_actions = performance
+        .iter()
+        .rev()
+        .take(OVERVIEW_TOP_ACTIONS)
+        .cloned()
+        .collect();
+    let best_actions = performance.into_iter().take(OVERVIEW_TOP_ACTIONS).collect();
+
+    let active_days = get_active_days(&state.pool, auth_user.user_id).await?;
+    let streaks = Schedule::Daily.evaluate(&active_days, today).streaks;
+
+    Ok(Json(OverviewResponse {
+        from,
+        to,
+        granularity,
+        today: TodayOverview {
+            active_actions,
+            completed,
+            completion_rate: (active_actions > 0).then(|| completed as f64 / active_actions as f64),
+        },
+        completions,
+        best_actions,
+        worst_actions,
+        streaks: OverviewStreaks {
+            current: streaks.current,
+            longest: streaks.longest,
+        },
+    }))
+}
+
+/// Streams everything the user has as a download; see [`export::stream`].
+pub async fn export_data(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, AppError> {
+    let user = get_user_by_id(&state.pool, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError(StatusCode::NOT_FOUND, "User not found".to_string()))?;
+    let format = query.format.unwrap_or_default();
+    let today = local_today(&state.pool, user.id).await?;
+    let disposition = format!(
+        "attachment; filename=\"rust-todo-{}.{}\"",
+        today,
+        format.extension()
+    );
+
+    let body = Body::from_stream(export::stream(state.pool.clone(), user, format));
+    Ok((
+        [
+            (CONTENT_TYPE, format.content_type().to_string()),
+            (CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// Imports an archive or another tracker's export; see [`import::run`].
+pub async fn import_data(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ImportQuery>,
+    body: Bytes,
+) -> Result<Json<ImportReport>, AppError> {
+    let report = import::run(
+        &state.pool,
+        auth_user.user_id,
+        query.format.unwrap_or_default(),
+        &body,
+        query.dry_run.unwrap_or(false),
+    )
+    .await?;
+    Ok(Json(report))
+}
+
+/// Changes since the client's cursor; see [`sync`].
+pub async fn get_sync(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SyncQuery>,
+) -> Result<Json<SyncChanges>, AppError> {
+    let changes = sync::pull(
+        &state.pool,
+        auth_user.user_id,
+        query.since.as_deref(),
+        query.limit,
+    )
+    .await?;
+    Ok(Json(changes))
+}
+
+/// Applies a batch of offline changes; see [`sync`].
+pub async fn push_sync(
+    auth_user: AuthUser,
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<SyncPushRequest>,
+) -> Result<Json<SyncPushResponse>, AppError> {
+    let response = sync::push(&state.pool, auth_user.user_id, req).await?;
+    Ok(Json(response))
 }
 
 async fn handle_404() -> impl IntoResponse {
@@ -273,12 +1314,44 @@ async fn main() {
     );
     info!("Database URL: {}", db_url);
 
+    // `rust-todo migrate ...` manages the schema without starting the server
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("migrate") {
+        let pool = sqlx::PgPool::connect(&db_url)
+            .await
+            .expect("Failed to connect to database");
+        if let Err(e) = migrate::run_command(&pool, &args[1..]).await {
+            error!("Migration failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Refuse to start with unusable or insecure token keys
+    let jwt_keys = JwtKeys::from_env().unwrap_or_else(|e| {
+        error!("Invalid JWT configuration: {}", e);
+        std::process::exit(1);
+    });
+
+    let notifier = notifier::notifier_from_env().unwrap_or_else(|e| {
+        error!("Invalid notifier configuration: {}", e);
+        std::process::exit(1);
+    });
+
     println!("Connecting to database...");
     let pool = db::init_db(&db_url)
         .await
         .expect("Failed to initialize database");
     info!("Database connection established");
 
+    reminder::spawn(
+        pool.clone(),
+        Arc::from(notifier),
+        Duration::from_secs(*REMINDER_INTERVAL_SECS),
+    );
+    webhook::spawn(pool.clone(), Duration::from_secs(*WEBHOOK_INTERVAL_SECS));
+    idempotency::spawn(pool.clone());
+
     let cors = CorsLayer::new()
         .allow_methods(Any)
         .allow_headers(Any)
@@ -289,16 +1362,74 @@ async fn main() {
         .make_span_with(trace::DefaultMakeSpan::new().level(Level::INFO))
         .on_response(trace::DefaultOnResponse::new().level(Level::INFO));
 
-    let app_state = Arc::new(AppState { pool });
+    let app_state = Arc::new(AppState { pool, jwt_keys });
 
-    let app = Router::new()
+    // Responses carrying tokens are never stored for replay, so keys are refused
+    let auth_routes = Router::new()
         .route("/api/register", post(register_user))
         .route("/api/login", post(login_user))
+        .route("/api/token/refresh", post(refresh_token))
+        .route_layer(middleware::from_fn(idempotency::reject));
+
+    // Imports take larger bodies, which the idempotency middleware reads too
+    let import_routes = Router::new()
+        .route(
+            "/api/import",
+            post(import_data).layer(DefaultBodyLimit::max(MAX_IMPORT_BYTES)),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            (app_state.clone(), MAX_IMPORT_BYTES),
+            idempotency::replay,
+        ));
+
+    let app = Router::new()
+        .route("/api/logout", post(logout))
+        .route("/api/logout-all", post(logout_all))
+        .route("/api/profile", get(get_profile))
+        .route("/api/profile", patch(update_profile))
         .route("/api/actions", post(create_action))
         .route("/api/actions", get(list_actions))
+        .route("/api/actions/order", put(reorder_actions))
         .route("/api/actions/:id", get(get_action))
+        .route("/api/actions/:id", patch(update_action))
+        .route("/api/actions/:id", delete(delete_action))
         .route("/api/actions/:id/records", get(get_action_records))
         .route("/api/actions/:id/finish", post(finish_action))
+        .route("/api/actions/:id/calendar", get(get_action_calendar))
+        .route("/api/actions/:id/reminders", get(list_action_reminders))
+        .route("/api/actions/:id/reminders", post(add_action_reminder))
+        .route("/api/records/:id", patch(update_record))
+        .route("/api/records/:id", delete(delete_record))
+        .route("/api/reminders/:id", delete(remove_reminder))
+        .route("/api/stats/overview", get(get_stats_overview))
+        .route("/api/export", get(export_data))
+        .route("/api/sync", get(get_sync))
+        .route("/api/sync", post(push_sync))
+        .route("/api/tags", post(create_tag))
+        .route("/api/tags", get(list_tags))
+        .route("/api/tags/:id", get(get_tag))
+        .route("/api/tags/:id", patch(update_tag))
+        .route("/api/tags/:id", delete(delete_tag))
+        .route("/api/groups", post(create_group))
+        .route("/api/groups", get(list_groups))
+        .route("/api/groups/:id", get(get_group))
+        .route("/api/groups/:id", patch(update_group))
+        .route("/api/groups/:id", delete(delete_group))
+        .route("/api/groups/:id/members", post(add_member))
+        .route("/api/groups/:id/members/:user_id", patch(update_member))
+        .route("/api/groups/:id/members/:user_id", delete(remove_member))
+        .route("/api/groups/:id/leaderboard", get(get_group_leaderboard))
+        .route("/api/webhooks", post(create_webhook))
+        .route("/api/webhooks", get(list_webhooks))
+        .route("/api/webhooks/:id", patch(update_webhook))
+        .route("/api/webhooks/:id", delete(delete_webhook))
+        .route("/api/webhooks/:id/deliveries", get(list_webhook_deliveries))
+        .route_layer(middleware::from_fn_with_state(
+            (app_state.clone(), MAX_BODY_BYTES),
+            idempotency::replay,
+        ))
+        .merge(import_routes)
+        .merge(auth_routes)
         .route("/api/coins", get(get_coins))
         .route("/api/blog/state", get(get_blog_state))
         .fallback(handle_404)
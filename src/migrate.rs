@@ -0,0 +1,89 @@
+//! Versioned schema migrations, embedded from the `migrations/` directory.
+//!
+//! Pending migrations are applied on every server start. The `migrate`
+//! subcommand inspects and changes the schema without starting the server:
+//!
+//! ```text
+//! rust-todo migrate status          list migrations and whether they are applied
+//! rust-todo migrate up              apply all pending migrations
+//! rust-todo migrate down [VERSION]  revert down to VERSION (default: the previous one)
+//! ```
+
+use sqlx::migrate::{Migrate, Migrator};
+use sqlx::PgPool;
+
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+pub async fn run_command(pool: &PgPool, args: &[String]) -> Result<(), String> {
+    match args.first().map(String::as_str) {
+        Some("status") | None => status(pool).await,
+        Some("up") => {
+            MIGRATOR.run(pool).await.map_err(|e| e.to_string())?;
+            println!("All migrations applied");
+            status(pool).await
+        }
+        Some("down") => {
+            let target = match args.get(1) {
+                Some(version) => version
+                    .parse()
+                    .map_err(|_| format!("Invalid target version: {}", version))?,
+                None => previous_version(pool).await?,
+            };
+            MIGRATOR
+                .undo(pool, target)
+                .await
+                .map_err(|e| e.to_string())?;
+            println!("Reverted to version {}", target);
+            status(pool).await
+        }
+        Some(other) => Err(format!(
+            "Unknown migrate command '{}', expected status, up or down",
+            other
+        )),
+    }
+}
+
+async fn applied_versions(pool: &PgPool) -> Result<Vec<(i64, Vec<u8>)>, String> {
+    let mut conn = pool.acquire().await.map_err(|e| e.to_string())?;
+    conn.ensure_migrations_table()
+        .await
+        .map_err(|e| e.to_string())?;
+    let applied = conn
+        .list_applied_migrations()
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(applied
+        .into_iter()
+        .map(|m| (m.version, m.checksum.into_owned()))
+        .collect())
+}
+
+/// The version just below the latest applied one, i.e. the target of a
+/// single-step rollback.
+async fn previous_version(pool: &PgPool) -> Result<i64, String> {
+    let applied = applied_versions(pool).await?;
+    let mut versions: Vec<i64> = applied.into_iter().map(|(version, _)| version).collect();
+    versions.sort_unstable();
+    versions.pop();
+    Ok(versions.pop().unwrap_or(0))
+}
+
+async fn status(pool: &PgPool) -> Result<(), String> {
+    let applied = applied_versions(pool).await?;
+
+    for migration in MIGRATOR
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+    {
+        let state = match applied.iter().find(|(v, _)| *v == migration.version) {
+            Some((_, checksum)) if *checksum == *migration.checksum => "applied",
+            Some(_) => "applied (modified since)",
+            None => "pending",
+        };
+        println!(
+            "{:>4}  {:<24} {}",
+            migration.version, migration.description, state
+        );
+    }
+    Ok(())
+}
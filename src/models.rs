@@ -1,6 +1,10 @@
 use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
 use sqlx::FromRow;
-use time::OffsetDateTime;
+use time::{Date, OffsetDateTime, Time};
+use uuid::Uuid;
+
+use crate::schedule::Schedule;
 
 mod timestamp_serializer {
     use serde::{Deserialize, Deserializer, Serializer};
@@ -50,6 +54,37 @@ mod optional_timestamp_serializer {
     }
 }
 
+// Calendar dates are exchanged as "YYYY-MM-DD"
+time::serde::format_description!(date_serializer, Date, "[year]-[month]-[day]");
+time::serde::format_description!(clock_time_serializer, Time, "[hour]:[minute]");
+
+/// Wraps a present value, including `null`, in `Some`; together with
+/// `#[serde(default)]` an absent field stays `None`.
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// Distinguishes an absent field (`None`) from an explicit `null` (`Some(None)`).
+fn deserialize_optional_date<'de, D>(deserializer: D) -> Result<Option<Option<Date>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    date_serializer::option::deserialize(deserializer).map(Some)
+}
+
+/// Like `deserialize_optional_date`, for fields that can be cleared with `null`.
+fn deserialize_optional<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
 #[derive(Deserialize)]
 pub struct QueryParams {
     pub ids: Option<String>,
@@ -62,6 +97,10 @@ pub struct User {
     pub username: String,
     #[serde(skip_serializing)]
     pub password_hash: String,
+    pub time_zone: String, // IANA name, e.g. "Europe/Berlin"
+    pub email: Option<String>,
+    #[serde(skip_serializing)]
+    pub token_version: i32,
     #[serde(with = "timestamp_serializer")]
     pub create_time: OffsetDateTime,
 }
@@ -76,17 +115,65 @@ pub struct LoginRequest {
 pub struct RegisterRequest {
     pub username: String,
     pub password: String,
+    pub time_zone: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateProfileRequest {
+    pub time_zone: Option<String>,
+    /// An address sets the email, `null` removes it.
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub email: Option<Option<String>>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct LoginResponse {
     pub token: String,
+    pub refresh_token: String,
+    pub expires_in: i64, // access token lifetime in seconds
     pub user: User,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenResponse {
+    pub token: String,
+    pub refresh_token: String,
+    pub expires_in: i64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
-    pub sub: i64, // user id
+    pub sub: i64,  // user id
+    pub exp: i64,  // expiration, unix timestamp
+    pub iat: i64,  // issued at, unix timestamp
+    pub jti: Uuid, // unique token id
+    pub sid: Uuid, // session id, the refresh token family issued alongside
+    pub ver: i32,  // users.token_version at issue time
+}
+
+#[derive(Debug, FromRow)]
+pub struct RefreshToken {
+    pub id: i64,
+    pub user_id: i64,
+    pub family_id: Uuid, // shared by every token rotated from the same login
+    pub expire_time: OffsetDateTime,
+    pub used_time: Option<OffsetDateTime>,
+    pub revoked_time: Option<OffsetDateTime>,
+}
+
+/// Result of presenting a refresh token to `db::rotate_refresh_token`.
+#[derive(Debug)]
+pub enum RefreshOutcome {
+    Rotated(RefreshToken),
+    /// The token was already used; its whole family is now revoked.
+    Reused,
+    /// Unknown, expired or revoked token.
+    Invalid,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
@@ -98,20 +185,175 @@ pub struct PracticeAction {
     pub create_time: OffsetDateTime,
     #[serde(with = "optional_timestamp_serializer")]
     pub last_finish_time: Option<OffsetDateTime>,
+    pub archived: bool,
+    #[serde(with = "date_serializer::option")]
+    pub paused_until: Option<Date>,
+    pub schedule: Json<Schedule>,
+    pub daily_target: i32, // completions needed per day
+    pub unit: Option<String>,
+    pub position: i32, // manual order among the user's actions outside of groups
+    pub group_id: Option<i64>, // shared with the group's members when set
+    #[sqlx(default)]
+    pub tags: Vec<String>,
 }
 
-#[derive(FromRow, Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct CreateActionRequest {
     pub name: String,
+    pub schedule: Option<Schedule>, // daily when omitted
+    pub daily_target: Option<i32>,  // 1 when omitted
+    pub unit: Option<String>,
+    pub tags: Option<Vec<String>>, // tag names, created when missing
+    pub group_id: Option<i64>,     // share with a group the user belongs to
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateActionRequest {
+    pub name: Option<String>,
+    pub archived: Option<bool>,
+    /// A date pauses the action up to and including that day, `null` resumes it.
+    #[serde(default, deserialize_with = "deserialize_optional_date")]
+    pub paused_until: Option<Option<Date>>,
+    pub schedule: Option<Schedule>,
+    pub daily_target: Option<i32>,
+    /// `null` removes the unit.
+    #[serde(default, deserialize_with = "deserialize_optional")]
+    pub unit: Option<Option<String>>,
+    /// Replaces the action's tags; missing tags are created.
+    pub tags: Option<Vec<String>>,
+}
+
+pub fn validate_daily_target(daily_target: i32) -> Result<(), String> {
+    if daily_target < 1 {
+        return Err("Daily target must be at least 1".to_string());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListActionsQuery {
+    pub include_archived: Option<bool>,
+    pub tag: Option<String>, // only actions carrying this tag
+    pub sort: Option<ActionSort>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ActionSort {
+    Manual,       // by position
+    Alphabetical, // by name
+    Recent,       // most recently finished first
+    Streak,       // longest current streak first
+}
+
+impl ActionSort {
+    pub fn as_sql(&self) -> &'static str {
+        match self {
+            ActionSort::Manual => "manual",
+            ActionSort::Alphabetical => "alphabetical",
+            ActionSort::Recent => "recent",
+            ActionSort::Streak => "streak",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReorderActionsRequest {
+    pub ids: Vec<i64>, // action ids in their new order
+}
+
+/// Result of `db::reorder_practice_actions`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReorderOutcome {
+    Reordered,
+    /// One of the actions does not exist or is not the user's.
+    NotFound,
+    /// One of the actions belongs to a group, which has no manual order.
+    GroupAction,
 }
 
 #[derive(FromRow, Debug, Serialize, Deserialize)]
 pub struct PracticeRecord {
     pub id: i64,
     pub action_id: i64,
+    pub user_id: i64, // who completed it; differs from the action's for groups
     #[serde(with = "timestamp_serializer")]
     pub finish_time: OffsetDateTime,
     pub note: Option<String>,
+    pub quantity: Option<i32>,
+    pub duration_seconds: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecordsQuery {
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    #[serde(default, with = "date_serializer::option")]
+    pub from: Option<Date>,
+    #[serde(default, with = "date_serializer::option")]
+    pub to: Option<Date>,
+}
+
+/// Position after the last record of a page, records being ordered by
+/// `(finish_time, id)` descending. Encoded as `<finish_time µs>.<id>`.
+#[derive(Debug, Clone, Copy)]
+pub struct RecordCursor {
+    pub finish_time: OffsetDateTime,
+    pub id: i64,
+}
+
+impl RecordCursor {
+    pub fn encode(&self) -> String {
+        format!(
+            "{}.{}",
+            self.finish_time.unix_timestamp_nanos() / 1000,
+            self.id
+        )
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let (micros, id) = cursor.split_once('.')?;
+        let micros: i128 = micros.parse().ok()?;
+        Some(RecordCursor {
+            finish_time: OffsetDateTime::from_unix_timestamp_nanos(micros * 1000).ok()?,
+            id: id.parse().ok()?,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecordPage {
+    pub records: Vec<PracticeRecord>,
+    pub next_cursor: Option<String>, // absent on the last page
+}
+
+/// Optional body of `POST /api/actions/:id/finish`.
+#[derive(Debug, Default, Deserialize)]
+pub struct FinishActionRequest {
+    /// Backfills a completion at this time instead of now.
+    #[serde(default, with = "optional_timestamp_serializer")]
+    pub finish_time: Option<OffsetDateTime>,
+    pub note: Option<String>,
+    pub quantity: Option<i32>,
+    pub duration_seconds: Option<i32>,
+}
+
+/// Result of `db::create_practice_record`.
+#[derive(Debug)]
+pub enum FinishOutcome {
+    Finished(PracticeRecord),
+    /// No such action, or the user cannot see it.
+    NotFound,
+    Archived,
+    /// The daily target of that local day is already reached.
+    TargetReached,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateRecordRequest {
+    pub note: Option<String>,
+    pub quantity: Option<i32>,
+    pub duration_seconds: Option<i32>,
 }
 
 #[derive(Debug, Serialize, FromRow, Deserialize)]
@@ -123,6 +365,780 @@ pub struct ActionWithStats {
     pub create_time: OffsetDateTime,
     #[serde(with = "optional_timestamp_serializer")]
     pub last_finish_time: Option<OffsetDateTime>,
+    pub archived: bool,
+    #[serde(with = "date_serializer::option")]
+    pub paused_until: Option<Date>,
+    pub schedule: Json<Schedule>,
+    pub daily_target: i32,
+    pub unit: Option<String>,
+    pub position: i32,
+    pub group_id: Option<i64>,
+    pub tags: Vec<String>,
+    /// Counts and streaks only cover the user's own completions, also for
+    /// group actions.
     pub total_finished: i64,
-    pub finished_today: bool,
+    pub today_count: i64,
+    pub finished_today: bool, // today_count reached daily_target
+    /// Whether the current occurrence of the schedule still needs a completion.
+    #[sqlx(default)]
+    pub due: bool,
+    #[sqlx(default)]
+    pub done_for_period: bool,
+    /// Consecutive occurrences met: days, scheduled weekdays or weeks.
+    #[sqlx(default)]
+    pub current_streak: i32,
+    #[sqlx(default)]
+    pub longest_streak: i32,
+    pub last_7_days: i64, // completions in the last 7 local days, today included
+    pub last_30_days: i64, // completions in the last 30 local days, today included
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CalendarQuery {
+    #[serde(default, with = "date_serializer::option")]
+    pub from: Option<Date>,
+    #[serde(default, with = "date_serializer::option")]
+    pub to: Option<Date>,
+}
+
+/// Completions of one action on one local day.
+#[derive(Debug, Serialize, FromRow)]
+pub struct CalendarDay {
+    #[serde(with = "date_serializer")]
+    pub date: Date,
+    pub count: i64,
+    pub done: bool, // count reached the action's daily target
+}
+
+#[derive(Debug, Serialize)]
+pub struct CalendarResponse {
+    pub action_id: i64,
+    #[serde(with = "date_serializer")]
+    pub from: Date,
+    #[serde(with = "date_serializer")]
+    pub to: Date,
+    pub days: Vec<CalendarDay>, // only days with completions, ascending
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    #[default]
+    Day,
+    Week,
+    Month,
+}
+
+impl Granularity {
+    /// Field name understood by Postgres' `date_trunc`.
+    pub fn as_sql(&self) -> &'static str {
+        match self {
+            Granularity::Day => "day",
+            Granularity::Week => "week",
+            Granularity::Month => "month",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OverviewQuery {
+    #[serde(default, with = "date_serializer::option")]
+    pub from: Option<Date>,
+    #[serde(default, with = "date_serializer::option")]
+    pub to: Option<Date>,
+    pub granularity: Option<Granularity>,
+}
+
+/// Completions within one day, week or month, identified by its first day.
+#[derive(Debug, Serialize, FromRow)]
+pub struct PeriodCount {
+    #[serde(with = "date_serializer")]
+    pub period: Date,
+    pub count: i64,
+}
+
+/// How well an action kept its schedule over the overview window.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ActionPerformance {
+    pub id: i64,
+    pub name: String,
+    pub done_days: i64, // days on which the daily target was reached
+    pub expected: i64,  // completions the schedule asked for
+    pub completion_rate: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TodayOverview {
+    pub active_actions: i64, // not archived or paused, and due or finished today
+    pub completed: i64,
+    pub completion_rate: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OverviewStreaks {
+    pub current: i32, // consecutive days with at least one completion
+    pub longest: i32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OverviewResponse {
+    #[serde(with = "date_serializer")]
+    pub from: Date,
+    #[serde(with = "date_serializer")]
+    pub to: Date,
+    pub granularity: Granularity,
+    pub today: TodayOverview,
+    pub completions: Vec<PeriodCount>,
+    pub best_actions: Vec<ActionPerformance>,
+    pub worst_actions: Vec<ActionPerformance>,
+    pub streaks: OverviewStreaks,
+}
+
+#[derive(FromRow, Debug, Serialize)]
+pub struct Tag {
+    pub id: i64,
+    pub name: String,
+    #[serde(with = "timestamp_serializer")]
+    pub create_time: OffsetDateTime,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TagRequest {
+    pub name: String,
+}
+
+pub fn normalize_tag_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Tag name must not be empty".to_string());
+    }
+    Ok(name.to_string())
+}
+
+/// Trims the given tag names and drops duplicates, sorted like the tags of
+/// listed actions.
+pub fn normalize_tags(tags: Vec<String>) -> Result<Vec<String>, String> {
+    let mut tags = tags
+        .iter()
+        .map(|name| normalize_tag_name(name))
+        .collect::<Result<Vec<_>, _>>()?;
+    tags.sort();
+    tags.dedup();
+    Ok(tags)
+}
+
+/// A tag with statistics summed over its non-archived actions.
+#[derive(Debug, Serialize, FromRow)]
+pub struct TagWithStats {
+    pub id: i64,
+    pub name: String,
+    #[serde(with = "timestamp_serializer")]
+    pub create_time: OffsetDateTime,
+    pub action_count: i64,
+    pub finished_today: i64, // actions that reached their daily target today
+    pub total_finished: i64,
+    pub last_7_days: i64,
+    pub last_30_days: i64,
+}
+
+#[derive(FromRow, Debug, Serialize)]
+pub struct Reminder {
+    pub id: i64,
+    pub action_id: i64,
+    #[serde(with = "clock_time_serializer")]
+    pub remind_at: Time, // time of day in the user's time zone
+    #[serde(with = "date_serializer::option")]
+    pub last_sent_date: Option<Date>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateReminderRequest {
+    #[serde(with = "clock_time_serializer")]
+    pub remind_at: Time,
+}
+
+/// A reminder claimed for sending, with what is needed to address it.
+#[derive(Debug, FromRow)]
+pub struct DueReminder {
+    pub id: i64,
+    pub action_id: i64,
+    pub action_name: String,
+    pub user_id: i64,
+    pub username: String,
+    pub email: Option<String>,
+    pub remind_at: Time,
+    pub attempts: i32,                    // failed sends so far today
+    pub previous_sent_date: Option<Date>, // restored if sending fails
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WebhookEvent {
+    #[serde(rename = "action.created")]
+    ActionCreated,
+    #[serde(rename = "record.created")]
+    RecordCreated,
+    #[serde(rename = "streak.broken")]
+    StreakBroken,
+}
+
+impl WebhookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::ActionCreated => "action.created",
+            WebhookEvent::RecordCreated => "record.created",
+            WebhookEvent::StreakBroken => "streak.broken",
+        }
+    }
+}
+
+#[derive(FromRow, Debug, Serialize)]
+pub struct Webhook {
+    pub id: i64,
+    pub url: String,
+    pub events: Vec<String>,
+    pub active: bool,
+    #[serde(with = "timestamp_serializer")]
+    pub create_time: OffsetDateTime,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateWebhookRequest {
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    pub secret: Option<String>, // generated when omitted
+}
+
+/// The secret is only returned when the webhook is created.
+#[derive(Debug, Serialize)]
+pub struct CreateWebhookResponse {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    pub secret: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateWebhookRequest {
+    pub url: Option<String>,
+    pub events: Option<Vec<WebhookEvent>>,
+    pub active: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed, // gave up after the last retry
+}
+
+impl DeliveryStatus {
+    pub fn as_sql(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeliveriesQuery {
+    pub status: Option<DeliveryStatus>,
+    pub before: Option<i64>, // only deliveries with a smaller id, for paging
+    pub limit: Option<i64>,
+}
+
+#[derive(FromRow, Debug, Serialize)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: i64,
+    pub event: String,
+    pub payload: serde_json::Value,
+    pub status: String,
+    pub attempts: i32,
+    #[serde(with = "timestamp_serializer")]
+    pub next_attempt_time: OffsetDateTime,
+    #[serde(with = "optional_timestamp_serializer")]
+    pub last_attempt_time: Option<OffsetDateTime>,
+    pub response_status: Option<i32>,
+    pub last_error: Option<String>,
+    #[serde(with = "timestamp_serializer")]
+    pub create_time: OffsetDateTime,
+}
+
+/// A delivery claimed for sending, with its endpoint.
+#[derive(FromRow, Debug)]
+pub struct PendingDelivery {
+    pub id: i64,
+    pub event: String,
+    pub payload: serde_json::Value,
+    pub attempts: i32,
+    pub create_time: OffsetDateTime,
+    pub url: String,
+    pub secret: String,
+}
+
+/// An action to check for a streak broken since it was last checked, for one
+/// of the users who can see it.
+#[derive(FromRow, Debug)]
+pub struct StreakCheck {
+    pub id: i64,
+    pub user_id: i64, // whose completions make the streak
+    pub name: String,
+    pub schedule: Json<Schedule>,
+    pub today: Date, // in the user's time zone
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupRole {
+    Owner, // created the group; can delete it
+    Admin, // manages members and the group's actions
+    Member,
+}
+
+impl GroupRole {
+    pub fn as_sql(&self) -> &'static str {
+        match self {
+            GroupRole::Owner => "owner",
+            GroupRole::Admin => "admin",
+            GroupRole::Member => "member",
+        }
+    }
+
+    pub fn from_sql(role: &str) -> Option<Self> {
+        match role {
+            "owner" => Some(GroupRole::Owner),
+            "admin" => Some(GroupRole::Admin),
+            "member" => Some(GroupRole::Member),
+            _ => None,
+        }
+    }
+
+    pub fn can_manage(&self) -> bool {
+        matches!(self, GroupRole::Owner | GroupRole::Admin)
+    }
+}
+
+/// A group as seen by one of its members.
+#[derive(FromRow, Debug, Serialize)]
+pub struct Group {
+    pub id: i64,
+    pub name: String,
+    #[serde(with = "timestamp_serializer")]
+    pub create_time: OffsetDateTime,
+    pub role: String, // the requesting user's role
+    pub member_count: i64,
+}
+
+#[derive(FromRow, Debug, Serialize)]
+pub struct GroupMember {
+    pub user_id: i64,
+    pub username: String,
+    pub role: String,
+    #[serde(with = "timestamp_serializer")]
+    pub join_time: OffsetDateTime,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GroupDetails {
+    #[serde(flatten)]
+    pub group: Group,
+    pub members: Vec<GroupMember>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GroupRequest {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddMemberRequest {
+    pub username: String,
+    pub role: Option<GroupRole>, // member when omitted
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateMemberRequest {
+    pub role: GroupRole,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LeaderboardQuery {
+    #[serde(default, with = "date_serializer::option")]
+    pub from: Option<Date>,
+    #[serde(default, with = "date_serializer::option")]
+    pub to: Option<Date>,
+}
+
+/// One member's completions of the group's actions, counted on their own
+/// local days.
+#[derive(FromRow, Debug, Serialize)]
+pub struct LeaderboardEntry {
+    pub rank: i64,
+    pub user_id: i64,
+    pub username: String,
+    pub completions: i64,
+    pub days_met: i64, // action-days on which the daily target was reached
+}
+
+#[derive(Debug, Serialize)]
+pub struct LeaderboardResponse {
+    #[serde(with = "date_serializer")]
+    pub from: Date,
+    #[serde(with = "date_serializer")]
+    pub to: Date,
+    pub entries: Vec<LeaderboardEntry>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Json, // full archive of actions and records
+    Csv, // one row per record
+    Ics, // completions and schedules as calendar events
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ics => "text/calendar; charset=utf-8",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ics => "ics",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    pub format: Option<ExportFormat>,
+}
+
+/// An action as exported, with the local day it was created on.
+#[derive(FromRow, Debug)]
+pub struct ExportAction {
+    #[sqlx(flatten)]
+    pub action: PracticeAction,
+    pub start_date: Date,
+}
+
+/// A record as exported, with what CSV and calendar rows show of its action.
+#[derive(FromRow, Debug)]
+pub struct ExportRecord {
+    #[sqlx(flatten)]
+    pub record: PracticeRecord,
+    pub action_name: String,
+    pub unit: Option<String>,
+    pub local_date: Date,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    #[default]
+    Json, // our own export archive
+    Loop, // Checkmarks.csv of a Loop Habit Tracker export
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    pub format: Option<ImportFormat>,
+    pub dry_run: Option<bool>, // report without saving
+}
+
+/// The parts of an exported archive that are imported; other fields, such
+/// as ids of the exporting account, are ignored.
+#[derive(Debug, Deserialize)]
+pub struct ImportArchive {
+    pub version: i32,
+    pub actions: Vec<ImportArchiveAction>,
+    /// Kept as raw values so that one malformed record only skips itself.
+    pub records: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportArchiveAction {
+    pub id: i64,
+    pub name: String,
+    #[serde(with = "timestamp_serializer")]
+    pub create_time: OffsetDateTime,
+    #[serde(default)]
+    pub archived: bool,
+    pub schedule: Option<Schedule>,
+    pub daily_target: Option<i32>,
+    pub unit: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportArchiveRecord {
+    pub action_id: i64,
+    #[serde(with = "timestamp_serializer")]
+    pub finish_time: OffsetDateTime,
+    pub note: Option<String>,
+    pub quantity: Option<i32>,
+    pub duration_seconds: Option<i32>,
+}
+
+/// An action to create for an import, validated and normalized.
+#[derive(Debug)]
+pub struct ImportAction {
+    pub name: String,
+    pub create_time: OffsetDateTime,
+    pub archived: bool,
+    pub schedule: Schedule,
+    pub daily_target: i32,
+    pub unit: Option<String>,
+    pub tags: Vec<String>,
+}
+
+/// An existing action records can be imported into.
+#[derive(FromRow, Debug)]
+pub struct ImportTarget {
+    pub id: i64,
+    pub name: String,
+    pub daily_target: i32,
+}
+
+/// A record ready to be inserted by an import.
+#[derive(Debug)]
+pub struct ImportedRecord {
+    pub action_id: i64,
+    pub finish_time: OffsetDateTime,
+    pub local_day: Date,
+    pub day_slot: i32,
+    pub note: Option<String>,
+    pub quantity: Option<i32>,
+    pub duration_seconds: Option<i32>,
+}
+
+/// A row that was not imported. `row` is the 1-based position in the
+/// archive's `records`, or the line number in a CSV file.
+#[derive(Debug, Serialize)]
+pub struct ImportIssue {
+    pub row: usize,
+    pub action: Option<String>,
+    pub reason: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub actions_created: i64,
+    pub actions_matched: i64, // existing actions with the same name
+    pub records_imported: i64,
+    pub duplicate_count: i64,
+    pub skipped_count: i64,
+    /// The first rows of each kind; the counts cover all of them.
+    pub duplicates: Vec<ImportIssue>,
+    pub skipped: Vec<ImportIssue>,
+}
+
+/// A claimed `Idempotency-Key` and, once its request finished, the response.
+#[derive(FromRow, Debug)]
+pub struct IdempotencyRecord {
+    pub fingerprint: String,
+    pub status: Option<i32>,
+    pub content_type: Option<String>,
+    pub body: Option<Vec<u8>>,
+}
+
+/// Position in the change history of `GET /api/sync`, encoded as
+/// `<change_seq>.<xid>`. Changes numbered up to `seq` were returned, except
+/// those of transactions still running at the time, all of which have a
+/// transaction id of at least `xid`; the next sync returns those again.
+#[derive(Debug, Clone, Copy)]
+pub struct SyncCursor {
+    pub seq: i64,
+    pub xid: i64,
+}
+
+impl SyncCursor {
+    pub fn encode(&self) -> String {
+        format!("{}.{}", self.seq, self.xid)
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let (seq, xid) = cursor.split_once('.')?;
+        Some(SyncCursor {
+            seq: seq.parse().ok()?,
+            xid: xid.parse().ok()?,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SyncQuery {
+    pub since: Option<String>, // everything when omitted
+    pub limit: Option<i64>,
+}
+
+/// An action as synced. Tags are not synced.
+#[derive(FromRow, Debug, Serialize)]
+pub struct SyncAction {
+    pub client_id: Uuid,
+    pub id: i64,
+    pub user_id: i64,
+    pub group_id: Option<i64>,
+    pub name: String,
+    #[serde(with = "timestamp_serializer")]
+    pub create_time: OffsetDateTime,
+    #[serde(with = "timestamp_serializer")]
+    pub update_time: OffsetDateTime,
+    #[serde(with = "optional_timestamp_serializer")]
+    pub last_finish_time: Option<OffsetDateTime>,
+    pub archived: bool,
+    #[serde(with = "date_serializer::option")]
+    pub paused_until: Option<Date>,
+    pub schedule: Json<Schedule>,
+    pub daily_target: i32,
+    pub unit: Option<String>,
+    pub position: i32,
+    #[serde(skip)]
+    pub change_seq: i64,
+}
+
+/// One of the user's own records as synced.
+#[derive(FromRow, Debug, Serialize)]
+pub struct SyncRecord {
+    pub client_id: Uuid,
+    pub action_client_id: Uuid,
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub record: PracticeRecord,
+    #[serde(with = "timestamp_serializer")]
+    pub update_time: OffsetDateTime,
+    #[serde(skip)]
+    pub change_seq: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncKind {
+    Action,
+    Record,
+}
+
+impl SyncKind {
+    pub fn as_sql(&self) -> &'static str {
+        match self {
+            SyncKind::Action => "action",
+            SyncKind::Record => "record",
+        }
+    }
+}
+
+/// A deleted action or record.
+#[derive(FromRow, Debug, Serialize)]
+pub struct SyncTombstone {
+    pub kind: String,
+    pub client_id: Uuid,
+    #[serde(with = "timestamp_serializer")]
+    pub delete_time: OffsetDateTime,
+    #[serde(skip)]
+    pub change_seq: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyncChanges {
+    pub actions: Vec<SyncAction>,
+    pub records: Vec<SyncRecord>,
+    pub deletions: Vec<SyncTombstone>,
+    pub cursor: String,
+    pub has_more: bool, // sync again with `cursor` for the rest
+}
+
+/// The full state of an action as changed by a client at `modified_time`.
+#[derive(Debug, Deserialize)]
+pub struct SyncActionChange {
+    pub client_id: Uuid,
+    #[serde(with = "timestamp_serializer")]
+    pub modified_time: OffsetDateTime,
+    pub name: String,
+    pub schedule: Option<Schedule>, // daily when omitted
+    pub daily_target: Option<i32>,  // 1 when omitted
+    pub unit: Option<String>,
+    #[serde(default)]
+    pub archived: bool,
+    #[serde(default, with = "date_serializer::option")]
+    pub paused_until: Option<Date>,
+    pub group_id: Option<i64>, // only used when the action is created
+}
+
+/// A completion made by a client, or changes of its details.
+#[derive(Debug, Deserialize)]
+pub struct SyncRecordChange {
+    pub client_id: Uuid,
+    pub action_client_id: Uuid,
+    #[serde(with = "timestamp_serializer")]
+    pub modified_time: OffsetDateTime,
+    #[serde(with = "timestamp_serializer")]
+    pub finish_time: OffsetDateTime, // fixed once the record exists
+    pub note: Option<String>,
+    pub quantity: Option<i32>,
+    pub duration_seconds: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SyncDeletion {
+    pub kind: SyncKind,
+    pub client_id: Uuid,
+}
+
+/// Body of `POST /api/sync`; changes are applied in the order listed, actions
+/// first, then records, then deletions.
+#[derive(Debug, Deserialize)]
+pub struct SyncPushRequest {
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub actions: Vec<SyncActionChange>,
+    #[serde(default)]
+    pub records: Vec<SyncRecordChange>,
+    #[serde(default)]
+    pub deletions: Vec<SyncDeletion>,
+}
+
+/// Result of `db::upsert_synced_action` and `db::update_synced_record`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SyncOutcome {
+    Applied,
+    /// The server has a change made after the client's.
+    Stale,
+    /// No such row the user can see, or the group of a new action.
+    NotFound,
+    /// The user may see the action but not change it.
+    Forbidden,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncStatus {
+    Applied,
+    Conflict, // the server's version was kept
+    Rejected,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyncResult {
+    pub kind: SyncKind,
+    pub client_id: Uuid,
+    pub status: SyncStatus,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyncPushResponse {
+    pub results: Vec<SyncResult>,
+    #[serde(flatten)]
+    pub changes: SyncChanges,
 }
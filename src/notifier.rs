@@ -0,0 +1,332 @@
+use async_trait::async_trait;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use reqwest::Client;
+use serde::Serialize;
+use std::env;
+use std::fmt;
+use std::time::Duration;
+use time::Time;
+use tracing::info;
+
+use crate::schedule::Schedule;
+
+time::serde::format_description!(clock_time_serializer, Time, "[hour]:[minute]");
+
+/// A reminder for one action, ready to be delivered.
+#[derive(Debug, Serialize)]
+pub struct Notification {
+    pub user_id: i64,
+    pub username: String,
+    #[serde(skip_serializing)]
+    pub email: Option<String>,
+    pub action_id: i64,
+    pub action_name: String,
+    #[serde(with = "clock_time_serializer")]
+    pub remind_at: Time,
+    #[serde(skip_serializing)]
+    pub schedule: Schedule,
+}
+
+impl Notification {
+    pub fn subject(&self) -> String {
+        format!("Reminder: {}", self.action_name)
+    }
+
+    /// Says what is still open in the action's current occurrence.
+    pub fn body(&self) -> String {
+        let open = match &self.schedule {
+            Schedule::Daily | Schedule::Weekdays { .. } | Schedule::EveryNDays { interval: 1 } => {
+                "is still open for today".to_string()
+            }
+            Schedule::EveryNDays { interval } => {
+                format!("has not been done in the last {} days", interval)
+            }
+            Schedule::TimesPerWeek { .. } => "is still open this week".to_string(),
+        };
+        format!(
+            "Hi {},\n\n\"{}\" {}.\n",
+            self.username, self.action_name, open
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum NotifyError {
+    /// Worth trying again, e.g. the server could not be reached.
+    Failed(String),
+    /// Trying again would fail the same way, e.g. the user has no email.
+    Undeliverable(String),
+}
+
+impl fmt::Display for NotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotifyError::Failed(message) | NotifyError::Undeliverable(message) => {
+                f.write_str(message)
+            }
+        }
+    }
+}
+
+/// A channel reminders are delivered through.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn notify(&self, notification: &Notification) -> Result<(), NotifyError>;
+}
+
+/// Writes reminders to the server log, useful during development.
+pub struct LogNotifier;
+
+#[async_trait]
+impl Notifier for LogNotifier {
+    async fn notify(&self, notification: &Notification) -> Result<(), NotifyError> {
+        info!(
+            "Reminder for user {}: {}",
+            notification.user_id,
+            notification.subject()
+        );
+        Ok(())
+    }
+}
+
+/// Posts each reminder as JSON to a fixed URL.
+pub struct WebhookNotifier {
+    client: Client,
+    url: String,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: String) -> Self {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("Failed to build HTTP client");
+        WebhookNotifier { client, url }
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    async fn notify(&self, notification: &Notification) -> Result<(), NotifyError> {
+        self.client
+            .post(&self.url)
+            .json(notification)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| NotifyError::Failed(format!("Webhook request failed: {}", e)))?;
+        Ok(())
+    }
+}
+
+/// Mails reminders to the user's `email`; fails for users without one.
+pub struct SmtpNotifier {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpNotifier {
+    /// Connects to `host` with `tls` being `starttls`, `tls` or `none`, on the
+    /// default port of that mode unless `port` is given.
+    pub fn new(
+        host: &str,
+        port: Option<u16>,
+        tls: &str,
+        from: Mailbox,
+        credentials: Option<Credentials>,
+    ) -> Result<Self, String> {
+        let mut builder = match tls {
+            "starttls" => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+                .map_err(|e| format!("Invalid SMTP_HOST: {}", e))?,
+            "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(host)
+                .map_err(|e| format!("Invalid SMTP_HOST: {}", e))?,
+            // Plain connection, for local SMTP servers such as MailHog
+            "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+            other => return Err(format!("Unsupported SMTP_TLS: {}", other)),
+        };
+        if let Some(port) = port {
+            builder = builder.port(port);
+        }
+        if let Some(credentials) = credentials {
+            builder = builder.credentials(credentials);
+        }
+
+        Ok(SmtpNotifier {
+            transport: builder.build(),
+            from,
+        })
+    }
+
+    /// Reads SMTP_HOST, SMTP_PORT, SMTP_FROM, SMTP_TLS (`starttls`, `tls` or
+    /// `none`) and optionally SMTP_USERNAME / SMTP_PASSWORD.
+    pub fn from_env() -> Result<Self, String> {
+        let host = env::var("SMTP_HOST").map_err(|_| "SMTP_HOST must be set".to_string())?;
+        let from = env::var("SMTP_FROM")
+            .map_err(|_| "SMTP_FROM must be set".to_string())?
+            .parse::<Mailbox>()
+            .map_err(|e| format!("Invalid SMTP_FROM: {}", e))?;
+        let tls = env::var("SMTP_TLS").unwrap_or_else(|_| "starttls".to_string());
+        let port = match env::var("SMTP_PORT") {
+            Ok(port) => Some(
+                port.parse()
+                    .map_err(|_| format!("Invalid SMTP_PORT: {}", port))?,
+            ),
+            Err(_) => None,
+        };
+        let credentials = match (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+            (Ok(username), Ok(password)) => Some(Credentials::new(username, password)),
+            _ => None,
+        };
+
+        SmtpNotifier::new(&host, port, &tls, from, credentials)
+    }
+}
+
+#[async_trait]
+impl Notifier for SmtpNotifier {
+    async fn notify(&self, notification: &Notification) -> Result<(), NotifyError> {
+        let email = notification.email.as_deref().ok_or_else(|| {
+            NotifyError::Undeliverable(format!("User {} has no email", notification.user_id))
+        })?;
+        let to = email
+            .parse::<Mailbox>()
+            .map_err(|e| NotifyError::Undeliverable(format!("Invalid email {}: {}", email, e)))?;
+
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(notification.subject())
+            .body(notification.body())
+            .map_err(|e| NotifyError::Undeliverable(format!("Failed to build email: {}", e)))?;
+
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| NotifyError::Failed(format!("Failed to send email: {}", e)))?;
+        Ok(())
+    }
+}
+
+/// Picks the notifier named by NOTIFIER: `log` (default), `webhook` or `smtp`.
+pub fn notifier_from_env() -> Result<Box<dyn Notifier>, String> {
+    match env::var("NOTIFIER").as_deref() {
+        Err(_) | Ok("log") => Ok(Box::new(LogNotifier)),
+        Ok("webhook") => {
+            let url = env::var("NOTIFIER_WEBHOOK_URL")
+                .map_err(|_| "NOTIFIER_WEBHOOK_URL must be set".to_string())?;
+            Ok(Box::new(WebhookNotifier::new(url)))
+        }
+        Ok("smtp") => Ok(Box::new(SmtpNotifier::from_env()?)),
+        Ok(other) => Err(format!("Unsupported NOTIFIER: {}", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// Accepts one SMTP session and returns its commands and message.
+    async fn fake_smtp_server(listener: TcpListener) -> (Vec<String>, Vec<String>) {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        let (mut commands, mut message) = (Vec::new(), Vec::new());
+
+        write.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            let verb = line.split(' ').next().unwrap().to_ascii_uppercase();
+            commands.push(line);
+            let reply: &[u8] = match verb.as_str() {
+                "EHLO" => b"250 localhost\r\n",
+                "MAIL" | "RCPT" => b"250 OK\r\n",
+                "DATA" => {
+                    write.write_all(b"354 Go ahead\r\n").await.unwrap();
+                    while let Some(line) = lines.next_line().await.unwrap() {
+                        if line == "." {
+                            break;
+                        }
+                        message.push(line);
+                    }
+                    b"250 Queued\r\n"
+                }
+                "QUIT" => {
+                    write.write_all(b"221 Bye\r\n").await.unwrap();
+                    break;
+                }
+                _ => b"502 Not implemented\r\n",
+            };
+            write.write_all(reply).await.unwrap();
+        }
+        (commands, message)
+    }
+
+    fn notification(email: Option<&str>) -> Notification {
+        Notification {
+            user_id: 1,
+            username: "ann".to_string(),
+            email: email.map(str::to_string),
+            action_id: 2,
+            action_name: "Read".to_string(),
+            remind_at: Time::from_hms(20, 0, 0).unwrap(),
+            schedule: Schedule::Daily,
+        }
+    }
+
+    #[tokio::test]
+    async fn smtp_notifier_sends_mail() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(fake_smtp_server(listener));
+
+        let from = "Todo <todo@example.com>".parse().unwrap();
+        let notifier = SmtpNotifier::new("127.0.0.1", Some(port), "none", from, None).unwrap();
+
+        let result = notifier
+            .notify(&notification(Some("ann@example.com")))
+            .await;
+        assert!(result.is_ok(), "{}", result.unwrap_err());
+
+        let (commands, message) = server.await.unwrap();
+        assert!(commands.contains(&"MAIL FROM:<todo@example.com>".to_string()));
+        assert!(commands.contains(&"RCPT TO:<ann@example.com>".to_string()));
+        assert!(message.contains(&"From: Todo <todo@example.com>".to_string()));
+        assert!(message.contains(&"To: ann@example.com".to_string()));
+        assert!(message.contains(&"Subject: Reminder: Read".to_string()));
+        assert!(message.contains(&"\"Read\" is still open for today.".to_string()));
+    }
+
+    #[test]
+    fn body_follows_the_schedule() {
+        let mut notification = notification(None);
+        assert!(notification
+            .body()
+            .contains("\"Read\" is still open for today."));
+
+        notification.schedule = Schedule::Weekdays { days: vec![1, 3] };
+        assert!(notification
+            .body()
+            .contains("\"Read\" is still open for today."));
+
+        notification.schedule = Schedule::EveryNDays { interval: 3 };
+        assert!(notification
+            .body()
+            .contains("\"Read\" has not been done in the last 3 days."));
+
+        notification.schedule = Schedule::TimesPerWeek { times: 3 };
+        assert!(notification
+            .body()
+            .contains("\"Read\" is still open this week."));
+    }
+
+    #[tokio::test]
+    async fn smtp_notifier_needs_an_email() {
+        let from = "todo@example.com".parse().unwrap();
+        let notifier = SmtpNotifier::new("127.0.0.1", None, "none", from, None).unwrap();
+        let result = notifier.notify(&notification(None)).await;
+        assert!(matches!(result, Err(NotifyError::Undeliverable(_))));
+    }
+}
@@ -0,0 +1,112 @@
+//! Sends reminders for actions that are still due once their time of day has
+//! passed in the user's time zone.
+//!
+//! Every check claims the reminders that are due by marking them as sent for
+//! the local day, so that each fires at most once a day even with several
+//! servers running. A reminder that fails to send for a reason that may pass,
+//! such as an unreachable mail server or a database error, is handed back and
+//! tried again after a delay that doubles with every failure, until it is
+//! given up for the day after `MAX_ATTEMPTS` failures.
+
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::time::MissedTickBehavior;
+use tracing::{error, warn};
+
+use crate::db::{claim_due_reminders, list_actions_with_stats, release_reminder};
+use crate::models::DueReminder;
+use crate::notifier::{Notification, Notifier, NotifyError};
+
+const MAX_ATTEMPTS: i32 = 5;
+/// Delay before the first retry, doubled for every further one.
+const BASE_BACKOFF_SECS: i64 = 60;
+
+/// Checks for due reminders every `period` for as long as the server runs.
+pub fn spawn(pool: PgPool, notifier: Arc<dyn Notifier>, period: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = send_due_reminders(&pool, notifier.as_ref()).await {
+                error!("Sending reminders failed: {}", e);
+            }
+        }
+    });
+}
+
+/// Sends every reminder whose time has passed today and whose action still
+/// needs a completion, handing back those to try again.
+async fn send_due_reminders(pool: &PgPool, notifier: &dyn Notifier) -> Result<(), sqlx::Error> {
+    for reminder in claim_due_reminders(pool).await? {
+        let (id, previous_sent_date) = (reminder.id, reminder.previous_sent_date);
+        let attempts = reminder.attempts + 1;
+        if send_reminder(pool, notifier, reminder).await {
+            continue;
+        }
+        if attempts >= MAX_ATTEMPTS {
+            warn!(
+                "Reminder {} failed {} times, giving up for today",
+                id, attempts
+            );
+            continue;
+        }
+        let retry_time = OffsetDateTime::now_utc()
+            + time::Duration::seconds(BASE_BACKOFF_SECS << (attempts - 1));
+        if let Err(e) = release_reminder(pool, id, previous_sent_date, attempts, retry_time).await {
+            error!("Releasing reminder {} failed: {}", id, e);
+        }
+    }
+    Ok(())
+}
+
+/// Sends the reminder if its action is due. Returns false if it should be
+/// tried again.
+async fn send_reminder(pool: &PgPool, notifier: &dyn Notifier, reminder: DueReminder) -> bool {
+    // Schedules are evaluated in Rust, so ask the stats whether it is due
+    let action = list_actions_with_stats(
+        pool,
+        reminder.user_id,
+        Some(reminder.action_id),
+        false,
+        None,
+        None,
+    )
+    .await;
+    let action = match action {
+        Ok(mut actions) => actions.pop().filter(|action| action.due),
+        Err(e) => {
+            error!("Checking reminder {} failed: {}", reminder.id, e);
+            return false;
+        }
+    };
+    let Some(action) = action else {
+        return true;
+    };
+
+    let notification = Notification {
+        user_id: reminder.user_id,
+        username: reminder.username,
+        email: reminder.email,
+        action_id: reminder.action_id,
+        action_name: reminder.action_name,
+        remind_at: reminder.remind_at,
+        schedule: action.schedule.0,
+    };
+    match notifier.notify(&notification).await {
+        Ok(()) => true,
+        Err(NotifyError::Undeliverable(e)) => {
+            warn!("Reminder {} not delivered: {}", reminder.id, e);
+            true
+        }
+        Err(NotifyError::Failed(e)) => {
+            warn!(
+                "Reminder {} not delivered, trying again: {}",
+                reminder.id, e
+            );
+            false
+        }
+    }
+}
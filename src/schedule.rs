@@ -0,0 +1,338 @@
+//! Recurrence schedules of practice actions and how completions are judged
+//! against them.
+//!
+//! Every schedule maps completion days onto a sequence of occurrences:
+//! calendar days for `daily` and `every_n_days`, scheduled days for
+//! `weekdays` and weeks for `times_per_week`. Streaks count consecutive
+//! occurrences that were met.
+
+use serde::{Deserialize, Serialize};
+use time::{Date, Duration};
+
+use crate::streak::{chain_streaks, Streaks};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Schedule {
+    /// Once every day.
+    #[default]
+    Daily,
+    /// Once within every `interval` days, counted from the last completion.
+    EveryNDays { interval: i32 },
+    /// On the given ISO weekdays, 1 = Monday through 7 = Sunday.
+    Weekdays { days: Vec<u8> },
+    /// On `times` different days of every week (Monday to Sunday).
+    TimesPerWeek { times: i32 },
+}
+
+/// Where an action stands relative to its schedule on a given day.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ScheduleStatus {
+    pub streaks: Streaks,
+    /// The current occurrence still needs a completion.
+    pub due: bool,
+    /// The current occurrence is already satisfied.
+    pub done_for_period: bool,
+}
+
+impl Schedule {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Schedule::Daily => Ok(()),
+            Schedule::EveryNDays { interval } if *interval >= 1 => Ok(()),
+            Schedule::EveryNDays { .. } => Err("interval must be at least 1".to_string()),
+            Schedule::Weekdays { days } if days.is_empty() => {
+                Err("days must not be empty".to_string())
+            }
+            Schedule::Weekdays { days } if days.iter().all(|d| (1..=7).contains(d)) => Ok(()),
+            Schedule::Weekdays { .. } => Err("days must be between 1 and 7".to_string()),
+            Schedule::TimesPerWeek { times } if (1..=7).contains(times) => Ok(()),
+            Schedule::TimesPerWeek { .. } => Err("times must be between 1 and 7".to_string()),
+        }
+    }
+
+    /// Evaluates the schedule for `today`. `done_days` are the local days on
+    /// which the action was completed, sorted ascending without duplicates.
+    pub fn evaluate(&self, done_days: &[Date], today: Date) -> ScheduleStatus {
+        let (occurrences, position, gap, done_for_period) = match self {
+            Schedule::Daily => {
+                let occurrences: Vec<i64> = done_days.iter().map(|d| day_index(*d)).collect();
+                let done = done_days.last() == Some(&today);
+                (occurrences, day_index(today), 1, done)
+            }
+            Schedule::EveryNDays { interval } => {
+                let interval = i64::from(*interval);
+                let occurrences: Vec<i64> = done_days.iter().map(|d| day_index(*d)).collect();
+                let position = day_index(today);
+                let done = occurrences
+                    .last()
+                    .is_some_and(|last| position - last < interval);
+                (occurrences, position, interval, done)
+            }
+            Schedule::Weekdays { days } => {
+                let mut occurrences: Vec<i64> = done_days
+                    .iter()
+                    .filter_map(|d| weekday_occurrence(days, *d))
+                    .collect();
+                occurrences.dedup();
+                // On an unscheduled day the next occurrence has not come yet,
+                // so the latest scheduled day decides.
+                let (position, latest) = match weekday_occurrence(days, today) {
+                    Some(occurrence) => (occurrence, occurrence),
+                    None => {
+                        let latest = latest_occurrence(days, today);
+                        (latest + 1, latest)
+                    }
+                };
+                let done = occurrences.last() == Some(&latest);
+                (occurrences, position, 1, done)
+            }
+            Schedule::TimesPerWeek { times } => {
+                let mut weeks: Vec<(i64, i32)> = Vec::new();
+                for day in done_days {
+                    let week = week_index(*day);
+                    match weeks.last_mut() {
+                        Some((last, count)) if *last == week => *count += 1,
+                        _ => weeks.push((week, 1)),
+                    }
+                }
+                let position = week_index(today);
+                let done = weeks
+                    .last()
+                    .is_some_and(|(week, count)| *week == position && count >= times);
+                let occurrences = weeks
+                    .into_iter()
+                    .filter(|(_, count)| count >= times)
+                    .map(|(week, _)| week)
+                    .collect();
+                (occurrences, position, 1, done)
+            }
+        };
+
+        ScheduleStatus {
+            streaks: chain_streaks(&occurrences, position, gap),
+            due: !done_for_period && self.is_scheduled(today),
+            done_for_period,
+        }
+    }
+
+    fn is_scheduled(&self, day: Date) -> bool {
+        match self {
+            Schedule::Weekdays { days } => days.contains(&day.weekday().number_from_monday()),
+            _ => true,
+        }
+    }
+}
+
+fn day_index(day: Date) -> i64 {
+    i64::from(day.to_julian_day())
+}
+
+/// Consecutive number of the Monday-based week containing `day`.
+fn week_index(day: Date) -> i64 {
+    let monday = day - Duration::days(i64::from(day.weekday().number_days_from_monday()));
+    day_index(monday).div_euclid(7)
+}
+
+/// Consecutive number of `day` among all scheduled weekdays, or `None` if the
+/// day is not scheduled.
+fn weekday_occurrence(days: &[u8], day: Date) -> Option<i64> {
+    let weekday = day.weekday().number_from_monday();
+    if !days.contains(&weekday) {
+        return None;
+    }
+    let mut scheduled: Vec<u8> = days.to_vec();
+    scheduled.sort_unstable();
+    scheduled.dedup();
+    let rank = scheduled.iter().filter(|d| **d < weekday).count() as i64;
+    Some(week_index(day) * scheduled.len() as i64 + rank)
+}
+
+/// Occurrence number of the latest scheduled day on or before `day`.
+fn latest_occurrence(days: &[u8], day: Date) -> i64 {
+    (0..7)
+        .filter_map(|back| weekday_occurrence(days, day - Duration::days(back)))
+        .next()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::date;
+
+    fn status(schedule: &Schedule, done_days: &[Date], today: Date) -> (i32, i32, bool, bool) {
+        let status = schedule.evaluate(done_days, today);
+        (
+            status.streaks.current,
+            status.streaks.longest,
+            status.due,
+            status.done_for_period,
+        )
+    }
+
+    #[test]
+    fn validate() {
+        assert!(Schedule::Daily.validate().is_ok());
+        assert!(Schedule::EveryNDays { interval: 0 }.validate().is_err());
+        assert!(Schedule::Weekdays { days: vec![] }.validate().is_err());
+        assert!(Schedule::Weekdays { days: vec![1, 8] }.validate().is_err());
+        assert!(Schedule::Weekdays { days: vec![1, 7] }.validate().is_ok());
+        assert!(Schedule::TimesPerWeek { times: 0 }.validate().is_err());
+        assert!(Schedule::TimesPerWeek { times: 8 }.validate().is_err());
+    }
+
+    #[test]
+    fn daily() {
+        let daily = Schedule::Daily;
+        let days = [
+            date!(2024 - 03 - 01),
+            date!(2024 - 03 - 02),
+            date!(2024 - 03 - 03),
+        ];
+        assert_eq!(
+            status(&daily, &days, date!(2024 - 03 - 03)),
+            (3, 3, false, true)
+        );
+        // Today is still open
+        assert_eq!(
+            status(&daily, &days, date!(2024 - 03 - 04)),
+            (3, 3, true, false)
+        );
+        assert_eq!(
+            status(&daily, &days, date!(2024 - 03 - 05)),
+            (0, 3, true, false)
+        );
+        assert_eq!(
+            status(&daily, &[], date!(2024 - 03 - 05)),
+            (0, 0, true, false)
+        );
+    }
+
+    #[test]
+    fn daily_across_the_year() {
+        let days = [
+            date!(2023 - 12 - 30),
+            date!(2023 - 12 - 31),
+            date!(2024 - 01 - 01),
+        ];
+        assert_eq!(
+            status(&Schedule::Daily, &days, date!(2024 - 01 - 01)),
+            (3, 3, false, true)
+        );
+    }
+
+    #[test]
+    fn every_n_days() {
+        let schedule = Schedule::EveryNDays { interval: 3 };
+        let days = [
+            date!(2024 - 03 - 01),
+            date!(2024 - 03 - 04),
+            date!(2024 - 03 - 07),
+        ];
+        // The window of the last completion covers the 7th to the 9th
+        assert_eq!(
+            status(&schedule, &days, date!(2024 - 03 - 09)),
+            (3, 3, false, true)
+        );
+        assert_eq!(
+            status(&schedule, &days, date!(2024 - 03 - 10)),
+            (3, 3, true, false)
+        );
+        assert_eq!(
+            status(&schedule, &days, date!(2024 - 03 - 11)),
+            (0, 3, true, false)
+        );
+
+        // Four days apart misses a window
+        let days = [date!(2024 - 03 - 01), date!(2024 - 03 - 05)];
+        assert_eq!(
+            status(&schedule, &days, date!(2024 - 03 - 05)),
+            (1, 1, false, true)
+        );
+    }
+
+    #[test]
+    fn weekdays_across_weeks() {
+        // Monday, Wednesday and Friday; 2024-03-01 is a Friday
+        let schedule = Schedule::Weekdays {
+            days: vec![1, 3, 5],
+        };
+        let days = [
+            date!(2024 - 03 - 01),
+            date!(2024 - 03 - 04),
+            date!(2024 - 03 - 06),
+        ];
+        // Thursday is not scheduled, and Wednesday was done
+        assert_eq!(
+            status(&schedule, &days, date!(2024 - 03 - 07)),
+            (3, 3, false, true)
+        );
+        // Friday is open
+        assert_eq!(
+            status(&schedule, &days, date!(2024 - 03 - 08)),
+            (3, 3, true, false)
+        );
+        // Friday was missed
+        assert_eq!(
+            status(&schedule, &days, date!(2024 - 03 - 09)),
+            (0, 3, false, false)
+        );
+        // Completions on unscheduled days do not count
+        let days = [date!(2024 - 03 - 05)];
+        assert_eq!(
+            status(&schedule, &days, date!(2024 - 03 - 05)),
+            (0, 0, false, false)
+        );
+    }
+
+    #[test]
+    fn times_per_week_with_the_week_still_open() {
+        let schedule = Schedule::TimesPerWeek { times: 2 };
+        // Twice in the week of Monday 2024-02-26, once in the next one so far
+        let days = [
+            date!(2024 - 02 - 27),
+            date!(2024 - 03 - 01),
+            date!(2024 - 03 - 04),
+        ];
+        assert_eq!(
+            status(&schedule, &days, date!(2024 - 03 - 06)),
+            (1, 1, true, false)
+        );
+
+        let days = [
+            date!(2024 - 02 - 27),
+            date!(2024 - 03 - 01),
+            date!(2024 - 03 - 04),
+            date!(2024 - 03 - 06),
+        ];
+        assert_eq!(
+            status(&schedule, &days, date!(2024 - 03 - 06)),
+            (2, 2, false, true)
+        );
+        // The next week is open, the one after breaks the streak
+        assert_eq!(
+            status(&schedule, &days, date!(2024 - 03 - 11)),
+            (2, 2, true, false)
+        );
+        assert_eq!(
+            status(&schedule, &days, date!(2024 - 03 - 18)),
+            (0, 2, true, false)
+        );
+    }
+
+    #[test]
+    fn times_per_week_across_the_year() {
+        // The week of Monday 2024-12-30 ends on 2025-01-05
+        let schedule = Schedule::TimesPerWeek { times: 2 };
+        let days = [date!(2024 - 12 - 31), date!(2025 - 01 - 02)];
+        assert_eq!(
+            status(&schedule, &days, date!(2025 - 01 - 03)),
+            (1, 1, false, true)
+        );
+        assert_eq!(
+            status(&schedule, &days, date!(2025 - 01 - 06)),
+            (1, 1, true, false)
+        );
+    }
+}
@@ -0,0 +1,77 @@
+//! Streak computation over the occurrences at which an action was completed.
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Streaks {
+    /// Length of the run that is still alive at the current position.
+    pub current: i32,
+    pub longest: i32,
+}
+
+/// Counts runs of completed occurrences. Two successive occurrences belong to
+/// the same run when they are at most `gap` apart, and the last run is still
+/// current when `position` is at most `gap` past it, i.e. the next
+/// occurrence can still extend it. `occurrences` must be sorted ascending
+/// without duplicates.
+pub fn chain_streaks(occurrences: &[i64], position: i64, gap: i64) -> Streaks {
+    let mut streaks = Streaks::default();
+    let mut run = 0;
+    let mut previous: Option<i64> = None;
+
+    for &occurrence in occurrences {
+        run = match previous {
+            Some(prev) if occurrence - prev <= gap => run + 1,
+            _ => 1,
+        };
+        streaks.longest = streaks.longest.max(run);
+        previous = Some(occurrence);
+    }
+
+    if let Some(last) = previous {
+        if position - last <= gap {
+            streaks.current = run;
+        }
+    }
+
+    streaks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn streaks(current: i32, longest: i32) -> Streaks {
+        Streaks { current, longest }
+    }
+
+    #[test]
+    fn no_occurrences() {
+        assert_eq!(chain_streaks(&[], 10, 1), streaks(0, 0));
+    }
+
+    #[test]
+    fn run_ending_at_position_is_current() {
+        assert_eq!(chain_streaks(&[1, 2, 3], 3, 1), streaks(3, 3));
+    }
+
+    #[test]
+    fn open_occurrence_keeps_the_run() {
+        assert_eq!(chain_streaks(&[1, 2, 3], 4, 1), streaks(3, 3));
+    }
+
+    #[test]
+    fn missed_occurrence_ends_the_run() {
+        assert_eq!(chain_streaks(&[1, 2, 3], 5, 1), streaks(0, 3));
+    }
+
+    #[test]
+    fn longest_run_can_be_an_earlier_one() {
+        assert_eq!(chain_streaks(&[1, 2, 3, 7, 8], 8, 1), streaks(2, 3));
+    }
+
+    #[test]
+    fn gap_joins_occurrences_further_apart() {
+        assert_eq!(chain_streaks(&[1, 3, 5, 9], 10, 2), streaks(1, 3));
+        assert_eq!(chain_streaks(&[1, 3, 5], 7, 2), streaks(3, 3));
+        assert_eq!(chain_streaks(&[1, 3, 5], 8, 2), streaks(0, 3));
+    }
+}
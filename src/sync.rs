@@ -0,0 +1,269 @@
+//! Sync for offline-first clients.
+//!
+//! Every change of an action or record takes the next number of a global
+//! sequence, and every deletion leaves a tombstone, so that a client can ask
+//! for everything that changed since its last sync. Clients address actions
+//! and records by client ids, UUIDs they generate for what they create
+//! offline, and push their changes in batches. Conflicts are resolved per
+//! action or record:
+//!
+//! - A deletion always wins; changes of a deleted action or record are
+//!   rejected, and deleting it again is a no-op.
+//! - Otherwise the change made last wins, by the client's `modified_time`
+//!   against the time of the last change on the server.
+//! - A new record follows the rules of finishing an action: one on a day
+//!   further back than `BACKFILL_DAYS` allows, or beyond the daily target,
+//!   is rejected.
+
+use axum::http::StatusCode;
+use sqlx::PgPool;
+use time::OffsetDateTime;
+
+use crate::db::{
+    check_finish_time, create_practice_record, delete_practice_action, delete_practice_record,
+    get_sync_cutoff, get_sync_horizon, get_sync_target, get_sync_tombstones, get_synced_actions,
+    get_synced_records, is_sync_tombstone, update_synced_record, upsert_synced_action,
+};
+use crate::models::{
+    validate_daily_target, FinishActionRequest, FinishOutcome, SyncActionChange, SyncChanges,
+    SyncCursor, SyncDeletion, SyncKind, SyncOutcome, SyncPushRequest, SyncPushResponse,
+    SyncRecordChange, SyncResult, SyncStatus,
+};
+use crate::AppError;
+
+const DEFAULT_LIMIT: i64 = 500;
+const MAX_LIMIT: i64 = 5000;
+/// Changes accepted in one push.
+const MAX_PUSH_CHANGES: usize = 1000;
+
+/// Changes since `since`, or everything without it, oldest first. At most
+/// `limit` changes are returned, apart from changes that were still being
+/// made during the previous sync.
+pub async fn pull(
+    pool: &PgPool,
+    user_id: i64,
+    since: Option<&str>,
+    limit: Option<i64>,
+) -> Result<SyncChanges, AppError> {
+    let since = since.map(decode_cursor).transpose()?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let since_seq = since.map_or(0, |c| c.seq);
+    let since_xid = since.map(|c| c.xid);
+
+    // Taken first, so that whatever the queries below miss has a later id
+    let horizon = get_sync_horizon(pool).await?;
+    let cutoff = get_sync_cutoff(pool, user_id, since_seq, since.is_some(), limit).await?;
+    let until_seq = cutoff.map(|seq| seq - 1);
+
+    let actions = get_synced_actions(pool, user_id, since_seq, until_seq, since_xid).await?;
+    let records = get_synced_records(pool, user_id, since_seq, until_seq, since_xid).await?;
+    // A first sync has nothing to delete
+    let deletions = match since {
+        Some(_) => get_sync_tombstones(pool, user_id, since_seq, until_seq, since_xid).await?,
+        None => Vec::new(),
+    };
+
+    let seq = until_seq.unwrap_or_else(|| {
+        actions
+            .iter()
+            .map(|a| a.change_seq)
+            .chain(records.iter().map(|r| r.change_seq))
+            .chain(deletions.iter().map(|t| t.change_seq))
+            .fold(since_seq, i64::max)
+    });
+
+    Ok(SyncChanges {
+        actions,
+        records,
+        deletions,
+        cursor: SyncCursor { seq, xid: horizon }.encode(),
+        has_more: cutoff.is_some(),
+    })
+}
+
+/// Applies the client's changes and returns the result of each, followed by
+/// the changes since the client's cursor, its own included.
+pub async fn push(
+    pool: &PgPool,
+    user_id: i64,
+    mut req: SyncPushRequest,
+) -> Result<SyncPushResponse, AppError> {
+    let count = req.actions.len() + req.records.len() + req.deletions.len();
+    if count > MAX_PUSH_CHANGES {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            format!("At most {} changes can be synced at once", MAX_PUSH_CHANGES),
+        ));
+    }
+    // Reject a bad cursor before changing anything
+    if let Some(cursor) = &req.cursor {
+        decode_cursor(cursor)?;
+    }
+    // A client clock running ahead must not win every later conflict
+    let now = OffsetDateTime::now_utc();
+    for change in &mut req.actions {
+        change.modified_time = change.modified_time.min(now);
+    }
+    for change in &mut req.records {
+        change.modified_time = change.modified_time.min(now);
+    }
+
+    let mut results = Vec::with_capacity(count);
+    for change in &req.actions {
+        let result = match validate_action(change) {
+            Ok(()) => apply_action(pool, user_id, change).await?,
+            Err(reason) => Err(reason),
+        };
+        results.push(sync_result(SyncKind::Action, change.client_id, result));
+    }
+    for change in &req.records {
+        let result = apply_record(pool, user_id, change).await?;
+        results.push(sync_result(SyncKind::Record, change.client_id, result));
+    }
+    for deletion in &req.deletions {
+        let result = apply_deletion(pool, user_id, deletion).await?;
+        results.push(sync_result(deletion.kind, deletion.client_id, result));
+    }
+
+    let changes = pull(pool, user_id, req.cursor.as_deref(), None).await?;
+    Ok(SyncPushResponse { results, changes })
+}
+
+fn decode_cursor(cursor: &str) -> Result<SyncCursor, AppError> {
+    SyncCursor::decode(cursor)
+        .ok_or_else(|| AppError(StatusCode::BAD_REQUEST, "Invalid cursor".to_string()))
+}
+
+/// What became of a change: `Ok(true)` if applied, `Ok(false)` if the
+/// server's version was kept, or why it was rejected.
+type Applied = Result<bool, String>;
+
+fn sync_result(kind: SyncKind, client_id: uuid::Uuid, result: Applied) -> SyncResult {
+    let (status, reason) = match result {
+        Ok(true) => (SyncStatus::Applied, None),
+        Ok(false) => (
+            SyncStatus::Conflict,
+            Some("Changed on the server after this change".to_string()),
+        ),
+        Err(reason) => (SyncStatus::Rejected, Some(reason)),
+    };
+    SyncResult {
+        kind,
+        client_id,
+        status,
+        reason,
+    }
+}
+
+fn validate_action(change: &SyncActionChange) -> Result<(), String> {
+    if change.name.trim().is_empty() {
+        return Err("Name must not be empty".to_string());
+    }
+    if let Some(schedule) = &change.schedule {
+        schedule
+            .validate()
+            .map_err(|e| format!("Invalid schedule: {}", e))?;
+    }
+    if let Some(daily_target) = change.daily_target {
+        validate_daily_target(daily_target)?;
+    }
+    Ok(())
+}
+
+async fn apply_action(
+    pool: &PgPool,
+    user_id: i64,
+    change: &SyncActionChange,
+) -> Result<Applied, AppError> {
+    if is_sync_tombstone(pool, user_id, SyncKind::Action, change.client_id).await? {
+        return Ok(Err("Action was deleted".to_string()));
+    }
+    Ok(match upsert_synced_action(pool, user_id, change).await? {
+        SyncOutcome::Applied => Ok(true),
+        SyncOutcome::Stale => Ok(false),
+        SyncOutcome::NotFound if change.group_id.is_some() => Err("Group not found".to_string()),
+        SyncOutcome::NotFound => Err("Action not found".to_string()),
+        SyncOutcome::Forbidden => Err("Not allowed to change this action".to_string()),
+    })
+}
+
+async fn apply_record(
+    pool: &PgPool,
+    user_id: i64,
+    change: &SyncRecordChange,
+) -> Result<Applied, AppError> {
+    if is_sync_tombstone(pool, user_id, SyncKind::Record, change.client_id).await? {
+        return Ok(Err("Record was deleted".to_string()));
+    }
+    match update_synced_record(pool, user_id, change).await? {
+        Some(SyncOutcome::Applied) => return Ok(Ok(true)),
+        Some(SyncOutcome::Stale) => return Ok(Ok(false)),
+        Some(_) => return Ok(Err("Record not found".to_string())),
+        None => {}
+    }
+
+    // A new completion
+    if let Some(reason) =
+        check_finish_time(pool, user_id, change.finish_time, *crate::BACKFILL_DAYS).await?
+    {
+        return Ok(Err(reason));
+    }
+    let Some(action_id) =
+        get_sync_target(pool, user_id, SyncKind::Action, change.action_client_id).await?
+    else {
+        let reason =
+            if is_sync_tombstone(pool, user_id, SyncKind::Action, change.action_client_id).await? {
+                "Action was deleted"
+            } else {
+                "Action not found"
+            };
+        return Ok(Err(reason.to_string()));
+    };
+    let req = FinishActionRequest {
+        finish_time: Some(change.finish_time),
+        note: change.note.clone(),
+        quantity: change.quantity,
+        duration_seconds: change.duration_seconds,
+    };
+    Ok(
+        match create_practice_record(
+            pool,
+            user_id,
+            action_id,
+            req,
+            Some((change.client_id, change.modified_time)),
+        )
+        .await?
+        {
+            FinishOutcome::Finished(_) => Ok(true),
+            FinishOutcome::NotFound => Err("Action not found".to_string()),
+            FinishOutcome::Archived => Err("Action is archived".to_string()),
+            FinishOutcome::TargetReached => Err("Already completed on that day".to_string()),
+        },
+    )
+}
+
+async fn apply_deletion(
+    pool: &PgPool,
+    user_id: i64,
+    deletion: &SyncDeletion,
+) -> Result<Applied, AppError> {
+    let Some(id) = get_sync_target(pool, user_id, deletion.kind, deletion.client_id).await? else {
+        // Deleting twice is fine
+        return Ok(
+            match is_sync_tombstone(pool, user_id, deletion.kind, deletion.client_id).await? {
+                true => Ok(true),
+                false => Err("Not found".to_string()),
+            },
+        );
+    };
+    let deleted = match deletion.kind {
+        SyncKind::Action => delete_practice_action(pool, user_id, id).await?,
+        SyncKind::Record => delete_practice_record(pool, user_id, id).await?,
+    };
+    Ok(match deleted {
+        true => Ok(true),
+        // Visible but neither the user's own nor managed by the user
+        false => Err("Not allowed to delete this action".to_string()),
+    })
+}
@@ -0,0 +1,337 @@
+use hmac::{Hmac, Mac};
+use hyper::client::connect::dns::Name;
+use rand::RngCore;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::{redirect, Client, Url};
+use serde_json::json;
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::task::JoinSet;
+use tokio::time::MissedTickBehavior;
+use tracing::{error, warn};
+
+use crate::db::{
+    claim_webhook_deliveries, finish_streak_check, get_completion_days, get_streak_checks,
+    record_delivery_attempt,
+};
+use crate::models::PendingDelivery;
+
+/// Attempts per delivery before it is marked as failed.
+const MAX_ATTEMPTS: i32 = 8;
+/// Delay before the first retry, doubled for every further one.
+const BASE_BACKOFF_SECS: i64 = 30;
+const DELIVERY_BATCH: i64 = 50;
+const DELIVERY_LEASE_SECS: i64 = 120;
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+const STREAK_CHECK_BATCH: i64 = 200;
+
+/// Delivers queued webhook events every `period` for as long as the server
+/// runs, and queues `streak.broken` events once per action and local day.
+pub fn spawn(pool: PgPool, period: Duration) {
+    // Only public addresses are connected to, and redirects are not
+    // followed, so that a webhook cannot reach into the server's network
+    let client = Client::builder()
+        .timeout(DELIVERY_TIMEOUT)
+        .dns_resolver(Arc::new(PublicResolver))
+        .redirect(redirect::Policy::none())
+        .no_proxy()
+        .build()
+        .expect("Failed to build HTTP client");
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = check_broken_streaks(&pool).await {
+                error!("Checking for broken streaks failed: {}", e);
+            }
+            if let Err(e) = deliver_pending(&pool, &client).await {
+                error!("Delivering webhooks failed: {}", e);
+            }
+        }
+    });
+}
+
+/// Checks that the URL is http(s) and does not name a host of the server's
+/// own network: `localhost` or an IP address that is not public. Host names
+/// are checked again after resolving them when delivering.
+pub fn validate_url(url: &str) -> Result<(), String> {
+    let url = Url::parse(url).map_err(|e| e.to_string())?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err("Only http and https are supported".to_string());
+    }
+    let host = url.host_str().ok_or("Host is missing")?;
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    if host == "localhost" || host.ends_with(".localhost") {
+        return Err("Host is not public".to_string());
+    }
+    // IPv6 literals are enclosed in brackets
+    let literal = host.trim_start_matches('[').trim_end_matches(']');
+    match literal.parse::<IpAddr>() {
+        Ok(ip) if !is_public_ip(ip) => Err("Host is not public".to_string()),
+        _ => Ok(()),
+    }
+}
+
+/// Whether the address is globally reachable, rather than e.g. loopback,
+/// private, link-local or reserved.
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) => is_public_ipv6(ip),
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        || a == 0 // this network
+        || (a == 100 && b & 0xc0 == 64) // shared address space
+        || (a == 192 && b == 0 && c == 0) // protocol assignments
+        || (a == 198 && b & 0xfe == 18) // benchmarking
+        || a >= 240) // reserved
+}
+
+fn is_public_ipv6(ip: Ipv6Addr) -> bool {
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        return is_public_ipv4(ip);
+    }
+    let segments = ip.segments();
+    // NAT64 addresses embed the IPv4 address they translate to
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        let [.., high, low] = segments;
+        return is_public_ipv4(Ipv4Addr::from(u32::from(high) << 16 | u32::from(low)));
+    }
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        || segments[0] & 0xfe00 == 0xfc00 // unique local
+        || segments[0] & 0xffc0 == 0xfe80 // link-local
+        || segments[0] == 0x2001 && segments[1] == 0xdb8) // documentation
+}
+
+/// Resolves host names like the system does, leaving out addresses that are
+/// not public.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_ip(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+/// A random secret for signing deliveries.
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    crate::auth::to_hex(&bytes)
+}
+
+/// Hex HMAC-SHA256 of `<timestamp>.<body>`, sent as `X-Webhook-Signature:
+/// sha256=<hex>` so that receivers can verify the sender and reject replays.
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    crate::auth::to_hex(&mac.finalize().into_bytes())
+}
+
+/// Queues `streak.broken` for actions whose streak was alive yesterday but
+/// not today.
+async fn check_broken_streaks(pool: &PgPool) -> Result<(), sqlx::Error> {
+    for check in get_streak_checks(pool, STREAK_CHECK_BATCH).await? {
+        let days = get_completion_days(pool, check.user_id, Some(check.id))
+            .await?
+            .remove(&check.id)
+            .unwrap_or_default();
+        let yesterday = check.today.previous_day().unwrap_or(check.today);
+        let before: Vec<_> = days.iter().copied().filter(|&d| d <= yesterday).collect();
+        let previous = check.schedule.evaluate(&before, yesterday).streaks.current;
+        let current = check.schedule.evaluate(&days, check.today).streaks.current;
+
+        let payload = (previous > 0 && current == 0).then(|| {
+            json!({
+                "action": { "id": check.id, "name": check.name },
+                "streak": previous,
+            })
+        });
+        finish_streak_check(pool, &check, payload.as_ref()).await?;
+    }
+    Ok(())
+}
+
+async fn deliver_pending(pool: &PgPool, client: &Client) -> Result<(), sqlx::Error> {
+    let deliveries = claim_webhook_deliveries(pool, DELIVERY_BATCH, DELIVERY_LEASE_SECS).await?;
+
+    let mut sends = JoinSet::new();
+    for delivery in deliveries {
+        let pool = pool.clone();
+        let client = client.clone();
+        sends.spawn(async move {
+            let id = delivery.id;
+            if let Err(e) = deliver(&pool, &client, delivery).await {
+                error!("Recording webhook delivery {} failed: {}", id, e);
+            }
+        });
+    }
+    while sends.join_next().await.is_some() {}
+    Ok(())
+}
+
+async fn deliver(
+    pool: &PgPool,
+    client: &Client,
+    delivery: PendingDelivery,
+) -> Result<(), sqlx::Error> {
+    let body = json!({
+        "id": delivery.id,
+        "event": delivery.event,
+        "created_at": delivery.create_time.unix_timestamp(),
+        "data": delivery.payload,
+    })
+    .to_string();
+    let timestamp = OffsetDateTime::now_utc().unix_timestamp();
+    let signature = sign(&delivery.secret, timestamp, &body);
+
+    // Addresses in the URL itself are not resolved, so check them here
+    if let Err(e) = validate_url(&delivery.url) {
+        let error = format!("Invalid webhook URL: {}", e);
+        return record_delivery_attempt(pool, delivery.id, false, None, Some(&error), None).await;
+    }
+    let result = client
+        .post(&delivery.url)
+        .header("Content-Type", "application/json")
+        .header("X-Webhook-Event", &delivery.event)
+        .header("X-Webhook-Delivery", delivery.id.to_string())
+        .header("X-Webhook-Timestamp", timestamp.to_string())
+        .header("X-Webhook-Signature", format!("sha256={}", signature))
+        .body(body)
+        .send()
+        .await;
+
+    let (response_status, error) = match result {
+        Ok(response) if response.status().is_success() => {
+            let status = i32::from(response.status().as_u16());
+            return record_delivery_attempt(pool, delivery.id, true, Some(status), None, None)
+                .await;
+        }
+        Ok(response) => (
+            Some(i32::from(response.status().as_u16())),
+            format!("Unexpected status {}", response.status()),
+        ),
+        Err(e) => (None, e.to_string()),
+    };
+
+    let attempts = delivery.attempts + 1;
+    let retry_time = (attempts < MAX_ATTEMPTS).then(|| {
+        OffsetDateTime::now_utc()
+            + time::Duration::seconds(BASE_BACKOFF_SECS << (attempts - 1).min(16))
+    });
+    warn!(
+        "Webhook delivery {} attempt {} failed: {}",
+        delivery.id, attempts, error
+    );
+    record_delivery_attempt(
+        pool,
+        delivery.id,
+        false,
+        response_status,
+        Some(&error),
+        retry_time,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn public_ips() {
+        for s in [
+            "93.184.216.34",
+            "8.8.8.8",
+            "2606:2800:220:1::1",
+            "::ffff:8.8.8.8",
+        ] {
+            assert!(is_public_ip(ip(s)), "{} is public", s);
+        }
+    }
+
+    #[test]
+    fn non_public_ips() {
+        for s in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "0.1.2.3",
+            "255.255.255.255",
+            "224.0.0.1",
+            "240.0.0.1",
+            "::",
+            "::1",
+            "fc00::1",
+            "fd12:3456::1",
+            "fe80::1",
+            "ff02::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+            "64:ff9b::10.0.0.1",
+        ] {
+            assert!(!is_public_ip(ip(s)), "{} is not public", s);
+        }
+    }
+
+    #[test]
+    fn urls() {
+        assert!(validate_url("https://example.com/hook").is_ok());
+        assert!(validate_url("http://93.184.216.34:8080/hook").is_ok());
+        assert!(validate_url("https://[2606:2800:220:1::1]/hook").is_ok());
+
+        assert!(validate_url("ftp://example.com/hook").is_err());
+        assert!(validate_url("not a url").is_err());
+        assert!(validate_url("http://localhost:3000/hook").is_err());
+        assert!(validate_url("http://api.localhost/hook").is_err());
+        assert!(validate_url("http://LOCALHOST./hook").is_err());
+        assert!(validate_url("http://127.0.0.1/hook").is_err());
+        assert!(validate_url("http://2130706433/hook").is_err()); // 127.0.0.1
+        assert!(validate_url("http://169.254.169.254/latest/meta-data").is_err());
+        assert!(validate_url("http://[::1]/hook").is_err());
+        assert!(validate_url("http://[::ffff:10.0.0.1]/hook").is_err());
+    }
+
+    #[tokio::test]
+    async fn resolver_leaves_out_non_public_addresses() {
+        let name: Name = "localhost".parse().unwrap();
+        assert!(PublicResolver.resolve(name).await.is_err());
+    }
+}